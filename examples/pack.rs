@@ -17,11 +17,12 @@ use std::{
 use spin::Mutex;
 use alloc::sync::Arc;
 use ones::file_system::{ BlockDevice, EasyFileSystem};
+use ones::memory::cache::{ Cache, map::Set, replace::Lru, get::Back };
 
 fn main() {
     let (source_path, target_path) = match_args();
 
-    let image: Arc<Mutex<dyn BlockDevice>> = Arc::new(Mutex::new(BlockFile(Mutex::new({
+    let file = BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
@@ -31,7 +32,10 @@ fn main() {
         f.set_len((config::SYS_SIZE * config::BLOCK_SIZE) as u64).unwrap();
 
         f
-    }))));
+    }));
+    let image: Arc<Mutex<dyn BlockDevice>> = Arc::new(Mutex::new(
+        Cache::new(Box::new(file), config::CACHE_SIZE, Box::new(Set::new(4)), Box::new(Lru::new()), Box::new(Back))
+    ));
 
     // 32MiB, at most 4095 files
     let efs = EasyFileSystem::new(image, config::SYS_SIZE as u32, 1);
@@ -56,6 +60,7 @@ fn main() {
         // write data to easy-fs
         inode.write_at(0, all_data.as_slice());
     }
+    efs.lock().sync();
 
     // list apps
     for app in root_inode.ls() {
@@ -102,6 +107,10 @@ impl BlockDevice for BlockFile {
             .expect("Error when seeking!");
         assert_eq!(file.write(buf).unwrap(), config::BLOCK_SIZE, "Not a complete block!");
     }
+
+    fn capacity(&self) -> usize {
+        config::SYS_SIZE
+    }
 }
 
 mod config {
@@ -109,4 +118,6 @@ mod config {
     pub const BLOCK_SIZE: usize = 512;
     /// 单位：块（block）
     pub const SYS_SIZE: usize = 32 * 2048;
+    /// 单位：块（block）
+    pub const CACHE_SIZE: usize = 64;
 }
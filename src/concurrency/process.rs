@@ -2,11 +2,13 @@
 The id of kernel process is 0.
 */
 
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use crate::{
-    memory::page::{ self, Table },
-    runtime::address_space::AddressSpace,
+    concurrency::thread::context::{ self, Context },
+    memory::{ Address, VirtPageNum, PageRange, page::{ self, Table } },
+    runtime::{ address_space::{ AddressSpace, Layout }, elf::{ self, Image } },
     Allocator
 };
 
@@ -18,44 +20,128 @@ pub trait Lib: Hal {
     fn new(parent: Option<usize>, address_space: AddressSpace) -> usize;
     /**
     Add init process.
+
+    不支持动态链接的程序
     */
-    fn from_elf(parent: Option<usize>, elf: &[u8]) -> usize {
-        let (address_space, data_offset) = AddressSpace::from_elf(&elf);
+    fn from_elf(parent: Option<usize>, elf: &[u8]) -> Result<usize, elf::Error> {
+        let (address_space, image) = AddressSpace::from_elf(elf, Layout::random(Self::entropy))?;
         let pid = Self::new(parent, address_space);
 
         access(|manager| {
             let process = manager.process[pid].as_mut().unwrap();
-            for i in 0..data_offset.len() { 
-                let segement= process.address_space.segement[i];
+            for (i, (_, _, range)) in image.load.iter().enumerate() {
+                let segement = process.address_space.segement[i];
 
-                Self::copy_data(&mut process.page_table, segement.range, &elf[data_offset[i].0..data_offset[i].1]);
+                Self::copy_data(&mut process.page_table, segement.range, &image.data[range.0..range.1]);
             }
         });
  
-        pid
+        Ok(pid)
+    }
+
+    /**
+    同 from_elf，段在首次访问时才分配页框并加载；动态链接的程序的解释器由 Hal::interpreter 读取
+    */
+    fn from_elf_lazy(parent: Option<usize>, elf: Arc<[u8]>) -> Result<usize, elf::Error> {
+        Ok(Self::new(parent, Self::load(elf)?))
+    }
+    /**
+    由 elf 得到按需加载的地址空间，布局随机化
+    */
+    fn load(elf: Arc<[u8]>) -> Result<AddressSpace, elf::Error> {
+        let interp = match Image::parse(elf.clone(), 0, false)?.interp {
+            Some(path) => Some(Self::interpreter(&path).ok_or(elf::Error::Unsupported("interpreter not found"))?),
+            None => None,
+        };
+
+        AddressSpace::from_elf_lazy(elf, interp, Layout::random(Self::entropy))
+    }
+    /**
+    以 elf 替换进程 pid 的地址空间与页表，返回原页表
+
+    在线程 tid 的 stack 页的 user stack 上建立含 argv、envp 与 auxv 的初始栈，context 的 pc 与 sp 分别设为入口与栈顶，其余寄存器清零；调用者负责切换到新的页表并刷新 TLB，之后再以 Table::destroy 销毁原页表
+    */
+    fn exec<C: context::Lib>(
+        pid: usize,
+        tid: usize,
+        elf: Arc<[u8]>,
+        argv: &[&[u8]],
+        envp: &[&[u8]],
+        stack: usize,
+        context: &mut Context,
+    ) -> Result<Table, elf::Error> {
+        let mut address_space = Self::load(elf)?;
+
+        let segment = address_space.stack_add(tid, stack, None);
+        let mut random = [0u8; 16];
+        random[..8].copy_from_slice(&Self::entropy().to_ne_bytes());
+        random[8..].copy_from_slice(&Self::entropy().to_ne_bytes());
+        let (sp, data) = address_space.initial_stack(segment.range.end.address(), argv, envp, random);
+        if data.len() > Address::address(stack) {
+            return Err(elf::Error::Unsupported("arguments exceed the stack"));
+        }
+
+        address_space.segement.push(segment);
+        let mut table = Self::exec_table(&address_space);
+        Self::copy_data(&mut table, PageRange::new(sp.floor(), segment.range.end), &data);
+
+        context.data_reg = [0; 32];
+        context.pc = address_space.entry.as_usize();
+        C::sp_set(context, sp.as_usize());
+
+        let table = access(|manager| {
+            let process = manager.process[pid].as_mut().unwrap();
+            process.address_space = address_space;
+            core::mem::replace(&mut process.page_table, table)
+        });
+
+        Ok(table)
     }
 
     fn new_kernel(address_space: AddressSpace) -> usize;
-    // /**
-    // fn fork(process: &mut Process) -> usize {
-    //     let id = Self::new_pid();
-
-    //     let thread = process.0.thread.iter().map(|x| x.clone()).collect();
-
-    //     let inner = Process {
-    //         id,
-    //         thread,
-    //         parent: Some(process.0.id),
-    //         address_space: process.0.address_space.clone(),
-    //         children: Vec::new(),
-    //     };
-    // }
-    // */
-    // fn fork(process: &mut Process) -> usize;
+    /**
+    复制进程，地址空间写时复制，返回子进程号
+
+    线程由调用者复制
+    */
+    fn fork(parent: usize) -> usize {
+        let (address_space, page_table) = access(|manager| {
+            let process = manager.process[parent].as_mut().unwrap();
+
+            (process.address_space.clone(), Self::fork_table(&mut process.page_table))
+        });
+        let pid = Process::new(Some(parent), address_space, page_table);
+
+        access(|manager| {
+            manager.process[parent].as_mut().unwrap().children.push(pid);
+        });
+
+        pid
+    }
 }
 
 pub trait Hal {
-    fn copy_data(table: &mut Table, range: (usize, usize), data: &[u8]);
+    fn copy_data(table: &mut Table, range: PageRange<VirtPageNum>, data: &[u8]);
+    /**
+    page::Lib::fork(table).unwrap()
+    */
+    fn fork_table(table: &mut Table) -> Table;
+    /**
+    为 exec 的新地址空间建立页表，同 Lib::new 映射 segement 中的段与平台相关的段
+    */
+    fn exec_table(address_space: &AddressSpace) -> Table;
+    /**
+    地址空间布局随机化的随机数来源，默认总是返回 0，即不随机化
+    */
+    fn entropy() -> usize {
+        0
+    }
+    /**
+    读取路径为 path 的解释器，默认不支持动态链接的程序
+    */
+    fn interpreter(_path: &str) -> Option<Arc<[u8]>> {
+        None
+    }
 }
 
 pub struct Process {
@@ -10,6 +10,8 @@ pub struct Scheduler {
     pub ready:     VecDeque<usize>,
     pub blocked:   VecDeque<usize>,
     pub completed: Vec<usize>,
+    /// 在阻塞之前就已被唤醒的 id
+    pub woken:     Vec<usize>,
 
     pub allocator: Allocator
 }
@@ -24,6 +26,7 @@ impl Scheduler {
             ready: VecDeque::new(),
             blocked: VecDeque::new(),
             completed: Vec::new(),
+            woken: Vec::new(),
 
             allocator: Allocator::new(1, cap - 1).unwrap()
         }
@@ -70,4 +73,65 @@ impl Scheduler {
 
         next
     }
+    /**
+    将正在运行的 id 移入阻塞队列，返回该 id
+
+    如果该 id 在此之前已被唤醒，则不阻塞，返回 None
+    */
+    pub fn block(&mut self) -> Option<usize> {
+        let id = self.running.unwrap();
+
+        if let Some(index) = self.woken.iter().position(|&woken| woken == id) {
+            self.woken.swap_remove(index);
+            return None;
+        }
+
+        self.running = None;
+        self.blocked.push_back(id);
+
+        Some(id)
+    }
+    /**
+    将 id 从阻塞队列移入就绪队列
+
+    该 id 尚未阻塞时记录唤醒，使之后的 block 立即返回
+    */
+    pub fn wake(&mut self, id: usize) {
+        if let Some(index) = self.blocked.iter().position(|&blocked| blocked == id) {
+            self.blocked.remove(index);
+            self.ready.push_back(id);
+        } else if !self.ready.contains(&id) && !self.woken.contains(&id) {
+            self.woken.push(id);
+        }
+    }
+    /**
+    忘记 id 尚未被 block 消耗的唤醒，例如等待的请求在阻塞之前就已完成
+    */
+    pub fn forget(&mut self, id: usize) {
+        self.woken.retain(|&woken| woken != id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Scheduler;
+
+    #[test]
+    fn woken() {
+        let mut scheduler = Scheduler::new(8);
+        let id = scheduler.add();
+        scheduler.running = scheduler.ready.pop_front();
+
+        // woken before blocking
+        scheduler.wake(id);
+        assert_eq!(scheduler.block(), None);
+        assert_eq!(scheduler.running, Some(id));
+
+        // the request completes before blocking, the wake-up is forgotten
+        scheduler.wake(id);
+        scheduler.forget(id);
+        assert_eq!(scheduler.block(), Some(id));
+        scheduler.wake(id);
+        assert_eq!(scheduler.ready.front(), Some(&id));
+    }
 }
@@ -62,7 +62,7 @@ impl Thread {
         
         process::access(|manager| {
             let process = manager.process[pid].as_mut().unwrap();
-            segement.push(process.address_space.stack(tid, ustack_size));
+            segement.push(process.address_space.stack_add(tid, ustack_size, None));
             segement.push(AddressSpace::idata(tid));
 
             let kernel = manager.process[0].as_mut().unwrap();
@@ -1,8 +1,16 @@
+/*!
+块缓存
+
+同一个块只有一个共享的缓存槽，缓存槽的数量可以配置，通过散列桶查找块号，使用 CLOCK 算法选择被替换的槽
+
+被修改的块不会在每次写入后立即写回，而是在块被替换或显式调用 block_cache_sync_all 时写回块设备。块设备本身可以是 memory::cache::Cache，此时写回的块仍需由块设备的 sync 写回磁盘
+*/
+
 use super::{BlockDevice, BLOCK_SZ};
-use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::hint::spin_loop;
 use lazy_static::*;
 use spin::Mutex;
 
@@ -75,69 +83,215 @@ impl Drop for BlockCache {
     }
 }
 
-const BLOCK_CACHE_SIZE: usize = 16;
+/**
+缓存槽
+
+used: CLOCK 算法的访问位
+*/
+struct Slot {
+    block_id: usize,
+    cache: Arc<Mutex<BlockCache>>,
+    used: bool,
+}
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    capacity: usize,
+    slot: Vec<Slot>,
+    /// 散列桶，保存槽的下标
+    bucket: Vec<Vec<usize>>,
+    /// CLOCK 算法的指针
+    hand: usize,
 }
 
 impl BlockCacheManager {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "Capacity of block cache must be positive.");
+
         Self {
-            queue: VecDeque::new(),
+            capacity,
+            slot: Vec::with_capacity(capacity),
+            bucket: vec![Vec::new(); capacity.next_power_of_two()],
+            hand: 0,
         }
     }
 
+    #[inline]
+    fn hash(&self, block_id: usize) -> usize {
+        block_id & (self.bucket.len() - 1)
+    }
+
+    fn search(&self, block_id: usize) -> Option<usize> {
+        self.bucket[self.hash(block_id)]
+            .iter()
+            .copied()
+            .find(|&index| self.slot[index].block_id == block_id)
+    }
+    /**
+    CLOCK 算法选择被替换的槽，跳过正在被使用的槽
+
+    所有槽均被使用时返回错误
+    */
+    fn victim(&mut self) -> Result<usize, ()> {
+        // two rounds: the first one may only clear used bits
+        for _ in 0..2 * self.capacity {
+            let index = self.hand;
+            self.hand = (self.hand + 1) % self.capacity;
+
+            let slot = &mut self.slot[index];
+            if Arc::strong_count(&slot.cache) > 1 {
+                continue;
+            }
+            if slot.used {
+                slot.used = false;
+            } else {
+                return Ok(index);
+            }
+        }
+
+        Err(())
+    }
+    /**
+    所有缓存槽均被使用时返回错误
+    */
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<Mutex<dyn BlockDevice>>,
-    ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
+    ) -> Result<Arc<Mutex<BlockCache>>, ()> {
+        if let Some(index) = self.search(block_id) {
+            let slot = &mut self.slot[index];
+            slot.used = true;
+            return Ok(Arc::clone(&slot.cache));
+        }
+
+        let index = if self.slot.len() < self.capacity {
+            self.slot.len()
         } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
-            }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
-                block_id,
-                Arc::clone(&block_device),
-            )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
+            let index = self.victim()?;
+            let hash = self.hash(self.slot[index].block_id);
+            self.bucket[hash].retain(|&i| i != index);
+            index
+        };
+
+        // load block into mem, the replaced one is written back when dropped
+        let block_cache = Arc::new(Mutex::new(BlockCache::new(
+            block_id,
+            Arc::clone(&block_device),
+        )));
+        let slot = Slot {
+            block_id,
+            cache: Arc::clone(&block_cache),
+            used: true,
+        };
+        if index == self.slot.len() {
+            self.slot.push(slot);
+        } else {
+            self.slot[index] = slot;
+        }
+        let hash = self.hash(block_id);
+        self.bucket[hash].push(index);
+
+        Ok(block_cache)
+    }
+
+    pub fn sync_all(&self) {
+        for slot in self.slot.iter() {
+            slot.cache.lock().sync();
         }
     }
 }
 
 lazy_static! {
     pub static ref BLOCK_CACHE_MANAGER: Mutex<BlockCacheManager> =
-        Mutex::new(BlockCacheManager::new());
+        Mutex::new(BlockCacheManager::new(config::CAPACITY));
 }
-
+/**
+重新设置缓存容量，原有的缓存会被写回
+*/
+pub fn block_cache_init(capacity: usize) {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    manager.sync_all();
+    *manager = BlockCacheManager::new(capacity);
+}
+/**
+所有缓存槽均被使用时等待
+*/
 pub fn get_block_cache(
     block_id: usize,
     block_device: Arc<Mutex<dyn BlockDevice>>,
 ) -> Arc<Mutex<BlockCache>> {
+    loop {
+        if let Ok(cache) = try_get_block_cache(block_id, Arc::clone(&block_device)) {
+            return cache;
+        }
+        spin_loop();
+    }
+}
+/**
+所有缓存槽均被使用时返回错误
+*/
+pub fn try_get_block_cache(
+    block_id: usize,
+    block_device: Arc<Mutex<dyn BlockDevice>>,
+) -> Result<Arc<Mutex<BlockCache>>, ()> {
     BLOCK_CACHE_MANAGER
         .lock()
         .get_block_cache(block_id, block_device)
 }
 
 pub fn block_cache_sync_all() {
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
+    BLOCK_CACHE_MANAGER.lock().sync_all();
+}
+pub(super) mod config {
+    /// 默认缓存容量，单位：块
+    pub const CAPACITY: usize = 16;
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use spin::Mutex;
+
+    use crate::file_system::{ BlockDevice, BLOCK_SZ };
+    use super::BlockCacheManager;
+
+    struct Disk(Vec<u8>);
+
+    impl BlockDevice for Disk {
+        fn read(&mut self, address: usize, cache: &mut [u8]) {
+            cache.copy_from_slice(&self.0[address * BLOCK_SZ..(address + 1) * BLOCK_SZ]);
+        }
+
+        fn write(&mut self, address: usize, cache: &[u8]) {
+            self.0[address * BLOCK_SZ..(address + 1) * BLOCK_SZ].copy_from_slice(cache);
+        }
+    }
+
+    #[test]
+    fn share() {
+        let disk = Arc::new(Mutex::new(Disk(vec![0; 8 * BLOCK_SZ])));
+        let device: Arc<Mutex<dyn BlockDevice>> = disk.clone();
+        let mut manager = BlockCacheManager::new(2);
+
+        let a = manager.get_block_cache(1, device.clone()).unwrap();
+        let b = manager.get_block_cache(1, device.clone()).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        a.lock().modify(0, |value: &mut u32| *value = 1);
+        b.lock().modify(4, |value: &mut u32| *value = 2);
+        assert_eq!(a.lock().read(0, |value: &[u32; 2]| *value), [1, 2]);
+        // not written back yet
+        assert_eq!(disk.lock().0[BLOCK_SZ], 0);
+
+        // both slots in use
+        let _c = manager.get_block_cache(2, device.clone()).unwrap();
+        assert!(manager.get_block_cache(3, device.clone()).is_err());
+
+        // block 1 is replaced and written back
+        drop(a);
+        drop(b);
+        manager.get_block_cache(3, device.clone()).unwrap();
+        assert_eq!(disk.lock().0[BLOCK_SZ..BLOCK_SZ + 8], [1, 0, 0, 0, 2, 0, 0, 0]);
     }
 }
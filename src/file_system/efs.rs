@@ -71,6 +71,7 @@ impl EasyFileSystem {
                 disk_inode.initialize(DiskInodeType::Directory);
             });
         block_cache_sync_all();
+        block_device.lock().sync();
         Arc::new(Mutex::new(efs))
     }
 
@@ -96,6 +97,12 @@ impl EasyFileSystem {
             })
     }
 
+    /// Write back all modified blocks.
+    pub fn sync(&self) {
+        block_cache_sync_all();
+        self.block_device.lock().sync();
+    }
+
     pub fn root(efs: &Arc<Mutex<Self>>) -> Inode {
         let block_device = Arc::clone(&efs.lock().block_device);
         // acquire efs lock temporarily
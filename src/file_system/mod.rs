@@ -9,13 +9,16 @@ mod layout;
 mod vfs;
 pub mod file;
 
+use alloc::boxed::Box;
 use alloc::sync::Arc;
+use core::sync::atomic::{ AtomicUsize, Ordering };
 use spin::Mutex;
 
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{ block_cache_sync_all, get_block_cache };
+use block_cache::{ block_cache_init, block_cache_sync_all, get_block_cache };
 pub use crate::peripheral::Block as BlockDevice;
+use crate::memory::cache::{ Cache, map, replace, get };
 pub use efs::EasyFileSystem;
 use layout::*;
 pub use vfs::Inode;
@@ -23,7 +26,9 @@ use file::File;
 
 pub trait Lib {
     fn init(disk: Arc<Mutex<dyn BlockDevice>>) {
-        let efs = EasyFileSystem::open(disk.clone());
+        block_cache_init(Self::cache_capacity());
+        let cache: Arc<Mutex<dyn BlockDevice>> = Arc::new(Mutex::new(Self::cache(disk)));
+        let efs = EasyFileSystem::open(cache);
         let mut handler = HANDLER.lock();
         *handler = Some(EasyFileSystem::root(&efs));
     }
@@ -49,6 +54,42 @@ pub trait Lib {
         }
     }
 
+    /**
+    块缓存的容量，单位：块
+    */
+    fn cache_capacity() -> usize {
+        block_cache::config::CAPACITY
+    }
+    /**
+    块设备的缓存，默认为 4 路组相联、CLOCK 替换和写回
+    */
+    fn cache(disk: Arc<Mutex<dyn BlockDevice>>) -> Cache {
+        Cache::new(
+            Box::new(disk),
+            config::CACHE_SIZE,
+            Box::new(map::Set::new(4)),
+            Box::new(replace::Clock::new()),
+            Box::new(get::Back),
+        )
+    }
+    /**
+    将所有被修改的块写回磁盘
+    */
+    fn sync() {
+        let mutex = HANDLER.lock();
+        if let Some(handler) = mutex.as_ref() {
+            handler.sync();
+        }
+    }
+    /**
+    周期性写回，可以在时钟中断中调用
+    */
+    fn tick() {
+        if TICK.fetch_add(1, Ordering::Relaxed) % config::SYNC_PERIOD == config::SYNC_PERIOD - 1 {
+            Self::sync();
+        }
+    }
+
     fn close_file() {
 
     }
@@ -118,4 +159,13 @@ impl Flag {
 use lazy_static::lazy_static;
 lazy_static! {
     static ref HANDLER: Mutex<Option<Inode>> = Mutex::new(None);
-}
\ No newline at end of file
+}
+
+static TICK: AtomicUsize = AtomicUsize::new(0);
+
+mod config {
+    /// 块设备的缓存的容量，单位：块
+    pub const CACHE_SIZE: usize = 16;
+    /// 写回周期，单位：次
+    pub const SYNC_PERIOD: usize = 64;
+}
@@ -128,7 +128,6 @@ impl Inode {
         });
 
         let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
         // return inode
         Some(Arc::new(Self::new(
             block_id,
@@ -163,12 +162,16 @@ impl Inode {
 
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
         let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
+        self.modify_disk_inode(|disk_inode| {
             self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
             disk_inode.write_at(offset, buf, &self.block_device)
-        });
+        })
+    }
+
+    /// Write back all modified blocks of the device.
+    pub fn sync(&self) {
         block_cache_sync_all();
-        size
+        self.block_device.lock().sync();
     }
 
     pub fn clear(&self) {
@@ -181,6 +184,5 @@ impl Inode {
                 fs.dealloc_data(data_block);
             }
         });
-        block_cache_sync_all();
     }
 }
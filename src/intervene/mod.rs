@@ -17,9 +17,17 @@
 
 pub mod data;
 
-use crate::{ concurrency::thread::context::Context, memory::Address };
+use crate::{
+    concurrency::{ process, thread::context::Context },
+    memory::{ Address, Flag, VirtAddr, VirtPageNum, address::config::VIRT_WIDTH, page::{ self, format::Selected } },
+    peripheral::plic,
+};
+use core::sync::atomic::{ AtomicBool, Ordering };
 use data::Data;
 
+/// 处于 Hal::user_copy 中的 hart
+static USER_COPY: [AtomicBool; config::HART] = [const { AtomicBool::new(false) }; config::HART];
+
 pub trait Lib: Hal {
     /**
     set kernel trap entry
@@ -74,13 +82,31 @@ pub trait Hal {
     fn service_set(address: usize);
     
     #[inline]
-    fn dist_user(idata: &mut Data, cause: Cause, _value: usize) {
+    fn dist_user(idata: &mut Data, cause: Cause, value: usize) {
         use Cause::*;
 
-        match cause {
+        match Self::classify(cause, value) {
             EnvCall => {
                 Self::syscall(&idata.cx);
             },
+            PageLoadFault => {
+                let page_number = Address::number(value);
+                Self::load_page(page_number);
+            },
+            PageStoreFault => {
+                let page_number = Address::number(value);
+                Self::store_page(page_number);
+            },
+            PageInstructionFault => {
+                let page_number = Address::number(value);
+                Self::fetch_page(page_number);
+            },
+            StackOverflow => {
+                Self::stack_overflow(value);
+            },
+            External => {
+                Self::external();
+            },
             _ => { panic!("Unsupported trap!"); }
         }
     }
@@ -111,18 +137,92 @@ pub trait Hal {
             External => {
                 Self::external();
             },
-            PageLoadFault => {
+            PageLoadFault | PageStoreFault | PageInstructionFault => {
                 let address = Self::value();
+                // only user memory accessed inside user_copy is handled as a user page fault
+                if !Self::is_user_copy(address) {
+                    panic!("Page fault in kernel, cause: {:?}, address: 0x{:x}", cause, address);
+                }
+
                 let page_number = Address::number(address);
-                Self::load_page(page_number);
+                match cause {
+                    PageLoadFault => Self::load_page(page_number),
+                    PageStoreFault => Self::store_page(page_number),
+                    _ => Self::fetch_page(page_number),
+                }
             },
-            Unknown => {
+            StackOverflow | Unknown => {
                 let value = Self::value();
                 panic!("Unsupported trap, value: 0x{:x}", value);
             }
         }
     }
     /**
+    在用户内存访问窗口中执行 f，其间内核态访问用户地址引起的缺页按用户缺页由 page_fault 处理，窗口之外的内核态缺页 panic
+
+    可以嵌套；与 page_fault 相同，调用时不能持有进程管理器的锁
+    */
+    fn user_copy<F, V>(f: F) -> V
+    where
+        F: FnOnce() -> V,
+    {
+        let hart = Self::hart();
+        let previous = USER_COPY[hart].swap(true, Ordering::Relaxed);
+        let value = f();
+        USER_COPY[hart].store(previous, Ordering::Relaxed);
+
+        value
+    }
+    /**
+    当前 hart 是否处于 user_copy 中，且 address 位于用户地址空间即虚拟地址空间的低半部分
+    */
+    fn is_user_copy(address: usize) -> bool {
+        USER_COPY[Self::hart()].load(Ordering::Relaxed) && address >> (VIRT_WIDTH - 1) == 0
+    }
+    /**
+    细分用户态的缺页原因：地址位于当前进程某个 user stack 的保护页面时为 Cause::StackOverflow，否则不变
+
+    在 trap 上下文中通过 process::access 获取进程管理器的锁，被打断的执行流不能持有该锁，用户态的 trap 总是满足
+    */
+    fn classify(cause: Cause, value: usize) -> Cause {
+        use Cause::*;
+
+        match cause {
+            PageLoadFault | PageStoreFault | PageInstructionFault => {
+                let Some(address) = VirtAddr::try_new(value) else {
+                    return cause;
+                };
+                let (pid, _) = Self::current();
+                let guard = process::access(|manager| {
+                    manager.process[pid].as_ref().is_some_and(|process| process.address_space.guard(address.floor()).is_some())
+                });
+
+                if guard { StackOverflow } else { cause }
+            },
+            _ => cause,
+        }
+    }
+    /**
+    栈溢出：输出诊断信息后终止当前线程
+    */
+    fn stack_overflow(address: usize) {
+        let (pid, tid) = Self::current();
+        log::error!("Stack overflow: thread {} of process {} accessed the guard page at {:#x}.", tid, pid, address);
+        Self::kill(tid);
+    }
+    /**
+    当前线程的 (pid, tid)
+
+    PlatformDependent
+    */
+    fn current() -> (usize, usize);
+    /**
+    终止线程 tid，不返回到该线程
+
+    PlatformDependent
+    */
+    fn kill(tid: usize);
+    /**
     intervene_data.cx.pc_add(4);
     let iid = intervene_data.cx.iid();
     let iarg = intervene_data.cx.iarg();
@@ -138,21 +238,133 @@ pub trait Hal {
     */
     fn breakpoiont(idata: &mut Data);
 
+    /**
+    外部中断：由 PLIC 认领，调用 plic::Handler::register 注册的处理函数（如 IrqBlk::register 注册的 handle_interrupt），之后通知完成
+    */
     fn external() {
-        todo!()
+        while plic::Handler::dispatch(Self::hart()) {}
+    }
+    /**
+    当前硬件线程的 id，小于 config::HART
+    */
+    fn hart() -> usize {
+        0
     }
 
-    fn load_page(_number: usize) {
-        todo!()
+    /**
+    刷新页号为 number 的页的 TLB 项
+
+    PlatformDependent
+    */
+    fn flush_tlb(number: usize);
+    /**
+    读缺页
+    */
+    fn load_page(number: usize) {
+        Self::page_fault(number, Flag::R);
+    }
+    /**
+    写缺页
+    */
+    fn store_page(number: usize) {
+        Self::page_fault(number, Flag::W);
+    }
+    /**
+    取指缺页
+    */
+    fn fetch_page(number: usize) {
+        Self::page_fault(number, Flag::X);
+    }
+    /**
+    由当前进程的 AddressSpace::resolve 处理缺页：换出的页换入，写时复制页复制，按需分配的页分配并填充，之后刷新 TLB
+
+    无法处理时输出诊断信息后终止当前线程；与 classify 相同，调用时不能持有进程管理器的锁
+    */
+    fn page_fault(number: usize, access: Flag) {
+        let (pid, tid) = Self::current();
+        let result = VirtPageNum::try_new(number).ok_or(page::Error::Invalid).and_then(|page_number| {
+            process::access(|manager| {
+                let process = manager.process[pid].as_mut().unwrap();
+                process.address_space.resolve::<Selected>(&mut process.page_table, page_number, access)
+            })
+        });
+
+        match result {
+            Ok(()) => Self::flush_tlb(number),
+            Err(error) => {
+                log::error!("Page fault: thread {} of process {} accessed page {:#x} ({:?}): {:?}.", tid, pid, number, access, error);
+                Self::kill(tid);
+            }
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Cause {
     EnvCall,
     Breakpoint,
     External,
     PageLoadFault,
+    PageStoreFault,
+    PageInstructionFault,
+    /// 访问了 user stack 下方的保护页面，由 Hal::classify 从缺页中细分得到
+    StackOverflow,
 
     Unknown
-}
\ No newline at end of file
+}
+mod config {
+    /// hart 数量的上限
+    pub const HART: usize = 8;
+}
+
+#[cfg(test)]
+mod test {
+    use core::sync::atomic::{ AtomicUsize, Ordering };
+    use crate::concurrency::thread::context::Context;
+    use super::{ Hal, Cause, data::{ Data, KernelInfo } };
+
+    static EXTERNAL: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    impl Hal for Handler {
+        fn cause() -> Cause { Cause::PageLoadFault }
+        fn value() -> usize { 0x1000 }
+        fn handler_set(_address: usize) {}
+        fn layout() -> (usize, usize, usize, usize) { (0, 0, 0, 0) }
+        fn service_set(_address: usize) {}
+        fn current() -> (usize, usize) { (0, 0) }
+        fn kill(_tid: usize) {}
+        fn syscall(_context: &Context) -> isize { 0 }
+        fn breakpoiont(_idata: &mut Data) {}
+        fn flush_tlb(_number: usize) {}
+
+        fn external() {
+            EXTERNAL.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn external() {
+        let mut idata = Data { cx: Context::empty(), ki: KernelInfo { addr_trans: 0, sp: 0, service: 0 } };
+
+        // a device interrupt taken while a user thread runs
+        Handler::dist_user(&mut idata, Cause::External, 0);
+        assert_eq!(EXTERNAL.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Page fault in kernel")]
+    fn kernel_fault() {
+        let nested = Handler::user_copy(|| {
+            Handler::user_copy(|| ());
+            Handler::is_user_copy(0x1000) && !Handler::is_user_copy(usize::MAX - 0xfff)
+        });
+        assert!(nested);
+        assert!(!Handler::is_user_copy(0x1000));
+
+        // outside user_copy the fault is a kernel bug
+        let mut idata = Data { cx: Context::empty(), ki: KernelInfo { addr_trans: 0, sp: 0, service: 0 } };
+        Handler::service_kernel(&mut idata);
+    }
+}
@@ -0,0 +1,346 @@
+/*!
+类型化的地址与页号
+
+- VirtAddr、PhysAddr：虚拟地址、物理地址
+- VirtPageNum、PhysPageNum：虚拟页号、物理页号（页框号）
+- PageRange：页号的左闭右开区间
+
+构造时检查地址宽度：虚拟地址须为 config::VIRT_WIDTH 位的符号扩展形式，物理地址须小于 2^config::PHYS_WIDTH，宽度由选定的页表格式 format::Selected 决定
+
+字段私有，只能经过检查构造；加减运算同样检查溢出与宽度，不合法时 panic
+
+# 结构体
+VirtAddr
+
+PhysAddr
+
+VirtPageNum
+
+PhysPageNum
+
+PageRange
+*/
+use core::fmt;
+use core::ops::{ Add, AddAssign, Sub };
+
+use super::Address;
+
+macro_rules! number {
+    ($name:ident, $prefix:literal, $check:expr) => {
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[repr(transparent)]
+        pub struct $name(usize);
+
+        impl $name {
+            /**
+            检查宽度后构造，不合法时 panic
+            */
+            #[inline]
+            pub fn new(value: usize) -> Self {
+                Self::try_new(value).unwrap_or_else(|| panic!("Invalid {}: {:#x}", stringify!($name), value))
+            }
+            /**
+            检查宽度后构造，不合法时返回 None
+            */
+            #[inline]
+            pub fn try_new(value: usize) -> Option<Self> {
+                let check: fn(usize) -> bool = $check;
+                check(value).then_some(Self(value))
+            }
+
+            #[inline]
+            pub const fn as_usize(self) -> usize {
+                self.0
+            }
+        }
+
+        impl From<$name> for usize {
+            #[inline]
+            fn from(value: $name) -> usize {
+                value.0
+            }
+        }
+
+        impl TryFrom<usize> for $name {
+            type Error = usize;
+
+            #[inline]
+            fn try_from(value: usize) -> Result<Self, usize> {
+                Self::try_new(value).ok_or(value)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!($prefix, ":{:#x}"), self.0)
+            }
+        }
+
+        impl Add<usize> for $name {
+            type Output = Self;
+
+            #[inline]
+            fn add(self, rhs: usize) -> Self {
+                self.0.checked_add(rhs).map(Self::new).expect(concat!(stringify!($name), " overflow"))
+            }
+        }
+
+        impl AddAssign<usize> for $name {
+            #[inline]
+            fn add_assign(&mut self, rhs: usize) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl Sub<usize> for $name {
+            type Output = Self;
+
+            #[inline]
+            fn sub(self, rhs: usize) -> Self {
+                self.0.checked_sub(rhs).map(Self::new).expect(concat!(stringify!($name), " underflow"))
+            }
+        }
+
+        impl Sub for $name {
+            type Output = usize;
+
+            #[inline]
+            fn sub(self, rhs: Self) -> usize {
+                self.0.checked_sub(rhs.0).expect(concat!(stringify!($name), " underflow"))
+            }
+        }
+    };
+}
+
+number!(VirtAddr, "VA", is_virt);
+number!(PhysAddr, "PA", is_phys);
+number!(VirtPageNum, "VPN", |number| Address::number(Address::address(number)) == number && is_virt(Address::address(number)));
+number!(PhysPageNum, "PPN", |number| Address::number(Address::address(number)) == number && is_phys(Address::address(number)));
+
+/**
+虚拟地址是否为 VIRT_WIDTH 位的符号扩展形式
+*/
+#[inline]
+fn is_virt(address: usize) -> bool {
+    let shift = usize::BITS as usize - config::VIRT_WIDTH;
+    (((address << shift) as isize) >> shift) as usize == address
+}
+
+#[inline]
+fn is_phys(address: usize) -> bool {
+    address >> config::PHYS_WIDTH == 0
+}
+
+impl VirtAddr {
+    #[inline]
+    pub fn floor(self) -> VirtPageNum {
+        VirtPageNum(Address::number(self.0))
+    }
+
+    #[inline]
+    pub fn ceil(self) -> VirtPageNum {
+        VirtPageNum(Address::ceil(self.0))
+    }
+
+    #[inline]
+    pub fn offset(self) -> usize {
+        Address::offset(self.0)
+    }
+
+    #[inline]
+    pub fn as_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+impl PhysAddr {
+    #[inline]
+    pub fn floor(self) -> PhysPageNum {
+        PhysPageNum(Address::number(self.0))
+    }
+
+    #[inline]
+    pub fn ceil(self) -> PhysPageNum {
+        PhysPageNum(Address::ceil(self.0))
+    }
+
+    #[inline]
+    pub fn offset(self) -> usize {
+        Address::offset(self.0)
+    }
+    /**
+    内核直接访问物理地址
+    */
+    #[inline]
+    pub fn as_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+impl VirtPageNum {
+    #[inline]
+    pub fn address(self) -> VirtAddr {
+        VirtAddr(Address::address(self.0))
+    }
+}
+
+impl PhysPageNum {
+    #[inline]
+    pub fn address(self) -> PhysAddr {
+        PhysAddr(Address::address(self.0))
+    }
+}
+
+impl From<VirtPageNum> for VirtAddr {
+    #[inline]
+    fn from(number: VirtPageNum) -> Self {
+        number.address()
+    }
+}
+
+impl From<PhysPageNum> for PhysAddr {
+    #[inline]
+    fn from(number: PhysPageNum) -> Self {
+        number.address()
+    }
+}
+
+/**
+可按 1 步进的页号
+*/
+pub trait Step: Copy + Ord + Add<usize, Output = Self> + Sub<Output = usize> {}
+
+impl Step for VirtPageNum {}
+
+impl Step for PhysPageNum {}
+
+/**
+页号区间 \[start, end)
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageRange<T: Step> {
+    pub start: T,
+    pub end: T,
+}
+
+impl<T: Step> PageRange<T> {
+    #[inline]
+    pub fn new(start: T, end: T) -> Self {
+        assert!(start <= end, "Start page number cannot be greater than end page number");
+        Self { start, end }
+    }
+    /**
+    由闭区间 \[start, last] 构造
+    */
+    #[inline]
+    pub fn inclusive(start: T, last: T) -> Self {
+        Self::new(start, last + 1)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    #[inline]
+    pub fn contains(&self, number: T) -> bool {
+        self.start <= number && number < self.end
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<T> {
+        Iter { current: self.start, end: self.end }
+    }
+}
+
+impl<T: Step> IntoIterator for PageRange<T> {
+    type Item = T;
+    type IntoIter = Iter<T>;
+
+    #[inline]
+    fn into_iter(self) -> Iter<T> {
+        self.iter()
+    }
+}
+
+pub struct Iter<T: Step> {
+    current: T,
+    end: T,
+}
+
+impl<T: Step> Iterator for Iter<T> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        if self.current < self.end {
+            let current = self.current;
+            self.current = current + 1;
+            Some(current)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.current;
+        (len, Some(len))
+    }
+}
+
+impl<T: Step> ExactSizeIterator for Iter<T> {}
+
+pub mod config {
+    use crate::memory::page::format::{ Format, Selected };
+
+    /// 虚拟地址的有效位数
+    pub const VIRT_WIDTH: usize = <Selected as Format>::VIRT_WIDTH;
+    /// 物理地址的有效位数
+    pub const PHYS_WIDTH: usize = <Selected as Format>::PHYS_WIDTH;
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+    use super::{ config, VirtAddr, PhysAddr, VirtPageNum, PhysPageNum, PageRange };
+
+    #[test]
+    fn check() {
+        let (virt, phys) = (config::VIRT_WIDTH, config::PHYS_WIDTH);
+        assert!(VirtAddr::try_new((1 << (virt - 1)) - 1).is_some());
+        assert!(VirtAddr::try_new(1 << (virt - 1)).is_none());
+        assert!(VirtAddr::try_new(!((1 << (virt - 1)) - 1)).is_some());
+        assert!(PhysAddr::try_new(1 << phys).is_none());
+        assert!(VirtPageNum::try_new((1 << 52) - 1).is_some());
+        assert!(VirtPageNum::try_new(1 << (virt - 13)).is_none());
+        assert!(PhysPageNum::try_new(1 << (phys - 12)).is_none());
+
+        let address = VirtAddr::new(0x1234);
+        assert_eq!(address.floor(), VirtPageNum(1));
+        assert_eq!(address.ceil(), VirtPageNum(2));
+        assert_eq!(address.offset(), 0x234);
+        assert_eq!(PhysAddr::from(PhysPageNum(3)), PhysAddr(0x3000));
+    }
+
+    #[test]
+    #[should_panic]
+    fn overflow() {
+        let _ = VirtPageNum::new((1 << (config::VIRT_WIDTH - 13)) - 1) + 1;
+    }
+
+    #[test]
+    fn range() {
+        let range = PageRange::inclusive(VirtPageNum(4), VirtPageNum(6));
+        assert_eq!(range.len(), 3);
+        assert!(range.contains(VirtPageNum(6)) && !range.contains(VirtPageNum(7)));
+        assert_eq!(range.iter().map(usize::from).collect::<Vec<_>>(), [4, 5, 6]);
+        assert_eq!(range.iter().step_by(2).len(), 2);
+        assert!(PageRange::new(VirtPageNum(1), VirtPageNum(1)).is_empty());
+    }
+}
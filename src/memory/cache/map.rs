@@ -1,29 +1,56 @@
-use alloc::vec::Vec;
+use core::ops::Range;
 
 use super::{ Map, Line };
 
+/**
+直接映射
+*/
 pub struct Direct;
 
 impl Map for Direct {
-    fn _cache_line_range(&self, line: &Vec<Line>, _address: usize) -> core::ops::Range<usize> {
-        0..line.len()
+    fn _cache_line_range(&self, line: &[Line], disk_line: usize) -> Range<usize> {
+        let index = disk_line % line.len();
+
+        index..index + 1
+    }
+}
+
+/**
+组相联
+
+way: 每组的行数，行数应为 way 的整数倍
+*/
+pub struct Set {
+    way: usize,
+}
+
+impl Set {
+    pub fn new(way: usize) -> Self {
+        assert!(way > 0, "Way of set-associative cache must be positive.");
+
+        Self { way }
     }
+}
 
-    fn _search(&self, line: &Vec<Line>, address: usize) -> Result<usize, ()> {
-        for i in 0..line.len() {
-            let valid = line[i].valid;
-            let tag = line[i].tag;
+impl Map for Set {
+    fn _check(&self, size: usize) -> bool {
+        size >= self.way && size.is_multiple_of(self.way)
+    }
 
-            if valid
-            && tag == address {
-                return Ok(i);
-            }
-        }
+    fn _cache_line_range(&self, line: &[Line], disk_line: usize) -> Range<usize> {
+        let set = disk_line % (line.len() / self.way);
 
-        Err(())
+        set * self.way..(set + 1) * self.way
     }
+}
+
+/**
+全相联
+*/
+pub struct Full;
 
-    fn _update_tag(&self, line: &mut Vec<Line>, offset: usize, address: usize) {
-        line[offset].tag = address; 
+impl Map for Full {
+    fn _cache_line_range(&self, line: &[Line], _disk_line: usize) -> Range<usize> {
+        0..line.len()
     }
 }
@@ -4,12 +4,18 @@ cache_line, offset
 disk_line
 
 地址结构（左高右低）：标签位（tag bit）、索引位（index bit）、行内偏移（line offset）
+
+# 策略
+- 映射（Map）：直接映射、组相联、全相联
+- 替换（Replace）：CLOCK、LRU、FIFO、随机
+- 写（Get）：写回、写直达
 */
 
 use alloc::vec;
 use alloc::vec::Vec;
 use alloc::boxed::Box;
 use core::any::Any;
+use core::ops::Range;
 
 use crate::peripheral as device;
 
@@ -20,8 +26,13 @@ pub mod get;
 pub struct Cache {
     /// 行地址的位数
     line_bit: usize,
-    line: Vec<Line>, // FIXME: 返回引用的设计，Option 的位置，尽量不要返回对枚举中数据的引用
-    
+    line: Vec<Line>,
+
+    /// 预读的行数，0 代表不预读
+    ahead: usize,
+    /// 上一次读取的 disk_line
+    last: usize,
+
     disk: Box<dyn device::Block>,
 
     map: Box<dyn Map>,
@@ -30,9 +41,21 @@ pub struct Cache {
 }
 
 pub trait Map: Send + Sync + Any {
-    fn _cache_line_range(&self, line: &Vec<Line>, disk_line: usize) -> core::ops::Range<usize>;
+    /**
+    行数为 size 时能否使用该映射
+    */
+    fn _check(&self, size: usize) -> bool {
+        size > 0
+    }
+    /**
+    disk_line 可以放置的 cache_line 范围
+    */
+    fn _cache_line_range(&self, line: &[Line], disk_line: usize) -> Range<usize>;
 
-    fn _search(&self, line: &Vec<Line>, disk_line: usize) -> Result<usize, ()>;
+    fn _search(&self, line: &[Line], disk_line: usize) -> Option<usize> {
+        self._cache_line_range(line, disk_line)
+            .find(|&i| line[i].valid && line[i].tag == disk_line)
+    }
 
     /**
     将字节地址（address）解析为 disk_line 和 offset
@@ -49,56 +72,250 @@ pub trait Map: Send + Sync + Any {
     /**
     注意 cache_line 应该位于 cache_line_range(address) 内
     */
-    fn _update_tag(&self, line: &mut Vec<Line>, cache_line: usize, disk_line: usize);
+    fn _update_tag(&self, line: &mut [Line], cache_line: usize, disk_line: usize) {
+        line[cache_line].tag = disk_line;
+    }
 }
 
 pub trait Replace: Send + Sync + Any {
     /**
+    范围内不存在无效行时才会被调用
+
     # 返回
     偏移量
     */
-    fn _replace(&self, line: &mut Vec<Line>, cache_line_range: core::ops::Range<usize>) -> usize;
+    fn _replace(&self, line: &mut [Line], cache_line_range: Range<usize>) -> usize;
+    /**
+    访问一行之后调用，hit 为 false 代表该行刚被载入
+    */
+    fn _touch(&self, line: &mut Line, _hit: bool) {
+        line.used = true;
+    }
 }
 
+/**
+写策略
+*/
 pub trait Get: Send + Sync + Any {
-    fn get_ref<'a>(&self, map: &Box<dyn Map>, replace: &Box<dyn Replace>, disk: &Box<dyn device::Block>, line: &'a mut Vec<Line>, disk_line: usize) -> &'a Vec<u8>;
-    
-    fn get_mut<'a>(&self, map: &Box<dyn Map>, replace: &Box<dyn Replace>, disk: &Box<dyn device::Block>, line: &'a mut Vec<Line>, disk_line: usize) -> &'a mut Vec<u8>;
+    /**
+    返回 disk_line 所在的 cache_line，未命中时选择一行替换并载入
+    */
+    fn get(&self, map: &dyn Map, replace: &dyn Replace, disk: &mut dyn device::Block, line: &mut [Line], disk_line: usize) -> usize {
+        if let Some(offset) = map._search(line, disk_line) {
+            replace._touch(&mut line[offset], true);
+
+            return offset;
+        }
+
+        let offset = self.victim(map, replace, disk, line, disk_line);
+        let current = &mut line[offset];
+        disk.read(disk_line, &mut current.data[..]);
+        replace._touch(current, false);
+
+        offset
+    }
+    /**
+    为 disk_line 选择一行，写回被替换的行并更新标签，调用者负责载入数据
+    */
+    fn victim(&self, map: &dyn Map, replace: &dyn Replace, disk: &mut dyn device::Block, line: &mut [Line], disk_line: usize) -> usize {
+        let range = map._cache_line_range(line, disk_line);
+        let offset = match range.clone().find(|&i| !line[i].valid) {
+            Some(offset) => offset,
+            None => replace._replace(line, range),
+        };
+
+        self.flush(disk, &mut line[offset]);
+        map._update_tag(line, offset, disk_line);
+        line[offset].valid = true;
+
+        offset
+    }
+    /**
+    行中的数据被修改之后调用
+    */
+    fn put(&self, disk: &mut dyn device::Block, line: &mut Line);
+    /**
+    写回被修改的行
+    */
+    fn flush(&self, disk: &mut dyn device::Block, line: &mut Line) {
+        if line.valid && line.dirty {
+            disk.write(line.tag, &line.data[..]);
+            line.dirty = false;
+        }
+    }
 }
 
 impl Cache {
-    pub fn new(disk: Box<dyn device::Block>, map: Box<dyn Map>, replace: Box<dyn Replace>, get: Box<dyn Get>) -> Self {
+    /**
+    # 参数
+    size: 行数，不能为 0；组相联时应为 way 的整数倍
+    */
+    pub fn new(disk: Box<dyn device::Block>, size: usize, map: Box<dyn Map>, replace: Box<dyn Replace>, get: Box<dyn Get>) -> Self {
+        assert!(size > 0 && map._check(size), "Size of cache does not fit the mapping.");
+
+        let line_bit = config::LINE_BIT;
+
         Self{
-            line_bit: 1,
-            line: vec![Line::new(); config::SIZE],
+            line_bit,
+            line: vec![Line::new(1 << line_bit); size],
+
+            ahead: config::AHEAD.min(size / 2),
+            last: usize::MAX,
 
             disk,
-            
+
             map,
             replace,
             get,
         }
     }
-
+    /**
+    读取从 address 开始的 number 个字节
+    */
     pub fn read(&mut self, address: usize, number: usize) -> Vec<u8> {
-        let (disk_line, offset) = self.map.parse(address, self.line_bit);
+        let mut data = vec![0; number];
+        self.read_to(address, &mut data);
+
+        data
+    }
 
-        let cache = self.get.get_ref(&self.map, &self.replace, &self.disk, &mut self.line, disk_line);
+    pub fn write(&mut self, address: usize, data: &[u8]) {
+        let line_size = 1 << self.line_bit;
 
-        cache[offset..number].to_vec()
+        let mut done = 0;
+        while done < data.len() {
+            let (disk_line, offset) = self.map.parse(address + done, self.line_bit);
+            let len = (line_size - offset).min(data.len() - done);
+
+            let cache_line = self.get.get(self.map.as_ref(), self.replace.as_ref(), self.disk.as_mut(), &mut self.line, disk_line);
+            let line = &mut self.line[cache_line];
+            line.data[offset..offset + len].copy_from_slice(&data[done..done + len]);
+            self.get.put(self.disk.as_mut(), line);
+
+            done += len;
+        }
     }
+    /**
+    设置预读的行数，0 代表不预读
 
-    pub fn write(&mut self, address: usize, data: &Vec<u8>) {
-        let (disk_line, offset) = self.map.parse(address, self.line_bit);
+    预读的行数不能超过总行数的一半，以免替换掉正在读取的行
+    */
+    pub fn ahead_set(&mut self, ahead: usize) {
+        self.ahead = ahead.min(self.line.len() / 2);
+    }
+    /**
+    写回所有被修改的行，连续的行合并为一次请求
+    */
+    pub fn sync(&mut self) {
+        let mut dirty: Vec<usize> = (0..self.line.len())
+            .filter(|&i| self.line[i].valid && self.line[i].dirty)
+            .collect();
+        dirty.sort_unstable_by_key(|&i| self.line[i].tag);
 
-        let cache = self.get.get_mut(&self.map, &self.replace, &self.disk, &mut self.line, disk_line);
+        let mut data = Vec::new();
+        let mut start = 0;
+        while start < dirty.len() {
+            let mut end = start + 1;
+            while end < dirty.len() && self.line[dirty[end]].tag == self.line[dirty[end - 1]].tag + 1 {
+                end += 1;
+            }
 
-        for i in 0..data.len() {
-            cache[offset + i] = data[i]
+            data.clear();
+            for &i in dirty[start..end].iter() {
+                data.extend_from_slice(&self.line[i].data);
+                self.line[i].dirty = false;
+            }
+            self.disk.write_blocks(self.line[dirty[start]].tag, &data);
+
+            start = end;
+        }
+    }
+
+    fn read_to(&mut self, address: usize, data: &mut [u8]) {
+        let line_size = 1 << self.line_bit;
+
+        let mut done = 0;
+        while done < data.len() {
+            let (disk_line, offset) = self.map.parse(address + done, self.line_bit);
+            let len = (line_size - offset).min(data.len() - done);
+
+            if disk_line == self.last.wrapping_add(1) && self.map._search(&self.line, disk_line).is_none() {
+                self.read_ahead(disk_line);
+            }
+            self.last = disk_line;
+
+            let cache_line = self.get.get(self.map.as_ref(), self.replace.as_ref(), self.disk.as_mut(), &mut self.line, disk_line);
+            data[done..done + len].copy_from_slice(&self.line[cache_line].data[offset..offset + len]);
+
+            done += len;
+        }
+    }
+    /**
+    顺序读取未命中时，将从 start 开始、不在缓存中的连续行（最多 1 + ahead 行）一次性读入
+    */
+    fn read_ahead(&mut self, start: usize) {
+        if self.ahead == 0 {
+            return;
+        }
+
+        let end = (start + 1 + self.ahead).min(self.disk.capacity());
+        let number = (start..end)
+            .take_while(|&disk_line| self.map._search(&self.line, disk_line).is_none())
+            .count();
+
+        let line_size = 1 << self.line_bit;
+        let mut data = vec![0; number * line_size];
+        self.disk.read_blocks(start, &mut data);
+
+        for (i, block) in data.chunks(line_size).enumerate() {
+            let disk_line = start + i;
+            let cache_line = self.get.victim(self.map.as_ref(), self.replace.as_ref(), self.disk.as_mut(), &mut self.line, disk_line);
+            let line = &mut self.line[cache_line];
+            line.data.copy_from_slice(block);
+            self.replace._touch(line, false);
         }
     }
 }
 
+/**
+以块为单位访问，块的大小应与行的大小相同
+*/
+impl device::Block for Cache {
+    fn read(&mut self, address: usize, cache: &mut [u8]) {
+        self.read_to(address << self.line_bit, cache);
+    }
+
+    fn write(&mut self, address: usize, cache: &[u8]) {
+        Cache::write(self, address << self.line_bit, cache);
+    }
+
+    fn block_size(&self) -> usize {
+        1 << self.line_bit
+    }
+
+    fn capacity(&self) -> usize {
+        self.disk.capacity()
+    }
+
+    fn read_blocks(&mut self, start: usize, cache: &mut [u8]) {
+        self.read_to(start << self.line_bit, cache);
+    }
+
+    fn write_blocks(&mut self, start: usize, cache: &[u8]) {
+        Cache::write(self, start << self.line_bit, cache);
+    }
+
+    fn sync(&mut self) {
+        Cache::sync(self);
+    }
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        self.sync();
+    }
+}
+
 #[derive(Clone)]
 pub struct Line {
     valid: bool,
@@ -106,24 +323,155 @@ pub struct Line {
     dirty: bool,
 
     tag: usize,
-    
+    /// 替换策略使用的时间戳，CLOCK 用组内第一行的 stamp 保存该组的指针
+    stamp: usize,
+
     data: Vec<u8>,
 }
 
 impl Line {
-    fn new() -> Self {
+    fn new(size: usize) -> Self {
         Self {
             valid: false,
             used: true,
             dirty: false,
 
             tag: 0,
+            stamp: 0,
 
-            data: vec![0; 512],
+            data: vec![0; size],
         }
     }
 }
 
 mod config {
-    pub const SIZE: usize = 16;
+    /// 行的大小为 512 B
+    pub const LINE_BIT: usize = 9;
+    /// 默认预读的行数
+    pub const AHEAD: usize = 4;
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::sync::atomic::{ AtomicUsize, Ordering };
+
+    use crate::peripheral::Block;
+    use super::{
+        Cache, Line, Replace,
+        get::{ Back, Through },
+        map::{ Direct, Full, Set },
+        replace::{ Clock, Fifo, Lru, Random },
+    };
+
+    struct Disk(Vec<u8>);
+
+    /// 记录请求次数
+    struct Counted(Disk, Arc<AtomicUsize>);
+
+    impl Block for Counted {
+        fn read(&mut self, address: usize, cache: &mut [u8]) {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.read(address, cache);
+        }
+
+        fn write(&mut self, address: usize, cache: &[u8]) {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.write(address, cache);
+        }
+
+        fn read_blocks(&mut self, start: usize, cache: &mut [u8]) {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            cache.copy_from_slice(&self.0.0[start * 512..start * 512 + cache.len()]);
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.capacity()
+        }
+    }
+
+    impl Block for Disk {
+        fn read(&mut self, address: usize, cache: &mut [u8]) {
+            cache.copy_from_slice(&self.0[address * 512..(address + 1) * 512]);
+        }
+
+        fn write(&mut self, address: usize, cache: &[u8]) {
+            self.0[address * 512..(address + 1) * 512].copy_from_slice(cache);
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len() / 512
+        }
+    }
+
+    #[test]
+    fn policy() {
+        let caches: Vec<Cache> = vec![
+            Cache::new(Box::new(Disk(vec![0; 64 * 512])), 4, Box::new(Direct), Box::new(Clock::new()), Box::new(Back)),
+            Cache::new(Box::new(Disk(vec![0; 64 * 512])), 4, Box::new(Set::new(2)), Box::new(Lru::new()), Box::new(Through)),
+            Cache::new(Box::new(Disk(vec![0; 64 * 512])), 4, Box::new(Full), Box::new(Fifo::new()), Box::new(Back)),
+            Cache::new(Box::new(Disk(vec![0; 64 * 512])), 4, Box::new(Full), Box::new(Random::new(7)), Box::new(Through)),
+        ];
+
+        for mut cache in caches {
+            // crosses line boundaries and forces replacement
+            for i in 0..64usize {
+                let data: Vec<u8> = (0..300).map(|j| (i * 3 + j) as u8).collect();
+                cache.write(i * 500, &data);
+            }
+            for i in 0..64usize {
+                let expect: Vec<u8> = (0..300).map(|j| (i * 3 + j) as u8).collect();
+                assert_eq!(cache.read(i * 500, 300), expect);
+            }
+
+            let mut block = [0u8; 512];
+            cache.sync();
+            Block::read(&mut cache, 1, &mut block);
+            assert_eq!(cache.read(512, 512), block);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn uneven() {
+        Cache::new(Box::new(Disk(vec![0; 64 * 512])), 6, Box::new(Set::new(4)), Box::new(Clock::new()), Box::new(Back));
+    }
+
+    #[test]
+    fn clock() {
+        let mut line = vec![Line::new(512); 4];
+        line.iter_mut().for_each(|line| line.used = false);
+
+        // each set has its own hand
+        assert_eq!(Clock::new()._replace(&mut line, 0..2), 0);
+        assert_eq!(Clock::new()._replace(&mut line, 2..4), 2);
+        assert_eq!(Clock::new()._replace(&mut line, 0..2), 1);
+        assert_eq!(Clock::new()._replace(&mut line, 0..2), 0);
+    }
+
+    #[test]
+    fn ahead() {
+        let data: Vec<u8> = (0..32 * 512).map(|i| (i / 512 + i) as u8).collect();
+        let request = Arc::new(AtomicUsize::new(0));
+        let disk = Counted(Disk(data.clone()), request.clone());
+        let mut cache = Cache::new(Box::new(disk), 8, Box::new(Full), Box::new(Lru::new()), Box::new(Back));
+
+        let mut block = [0u8; 512];
+        for i in 0..32 {
+            Block::read(&mut cache, i, &mut block);
+            assert_eq!(block[..], data[i * 512..(i + 1) * 512]);
+        }
+        // one request per window of 5 lines
+        assert_eq!(request.load(Ordering::Relaxed), 7);
+
+        cache.ahead_set(0);
+        request.store(0, Ordering::Relaxed);
+        for i in 0..8 {
+            Block::read(&mut cache, i, &mut block);
+        }
+        assert_eq!(request.load(Ordering::Relaxed), 8);
+    }
 }
@@ -1,33 +1,129 @@
-use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{ AtomicUsize, Ordering };
 
 use super::{ Line, Replace };
 
+/**
+CLOCK
+
+每组有各自的指针，保存在组内第一行的 stamp 中
+*/
+#[derive(Default)]
 pub struct Clock;
 
+impl Clock {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
 impl Replace for Clock {
-    fn _replace(&self, line: &mut Vec<Line>, mut offset_range: core::ops::Range<usize>) -> usize { // FIXME: where mut?
-        let first = if let Some(first) = offset_range.next() {
-            let valid = line[first].valid;
+    fn _replace(&self, line: &mut [Line], range: Range<usize>) -> usize {
+        let len = range.len();
+
+        // the second round always finds a line whose used bit has been cleared
+        loop {
+            let hand = line[range.start].stamp;
+            line[range.start].stamp = hand.wrapping_add(1);
+            let offset = range.start + hand % len;
 
-            if valid && line[first].used == false {
-                return first;
+            if line[offset].used {
+                line[offset].used = false;
             } else {
-                line[first].used = false;
+                return offset;
             }
+        }
+    }
+}
 
-            first
-        } else { 0 }; // FIXME
+/**
+最近最少使用（least recently used）
+*/
+pub struct Lru {
+    time: AtomicUsize,
+}
 
-        for i in offset_range {
-            let valid = line[i].valid;
+impl Lru {
+    pub fn new() -> Self {
+        Self { time: AtomicUsize::new(0) }
+    }
+}
 
-            if valid && line[i].used == false {
-                return i;
-            } else {
-                line[i].used = false;
-            }
+impl Default for Lru {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Replace for Lru {
+    fn _replace(&self, line: &mut [Line], range: Range<usize>) -> usize {
+        range.min_by_key(|&i| line[i].stamp).unwrap()
+    }
+
+    fn _touch(&self, line: &mut Line, _hit: bool) {
+        line.used = true;
+        line.stamp = self.time.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/**
+先进先出（first in first out）
+*/
+pub struct Fifo {
+    time: AtomicUsize,
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Self { time: AtomicUsize::new(0) }
+    }
+}
+
+impl Default for Fifo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Replace for Fifo {
+    fn _replace(&self, line: &mut [Line], range: Range<usize>) -> usize {
+        range.min_by_key(|&i| line[i].stamp).unwrap()
+    }
+
+    fn _touch(&self, line: &mut Line, hit: bool) {
+        line.used = true;
+        if !hit {
+            line.stamp = self.time.fetch_add(1, Ordering::Relaxed);
         }
-  
-        first
+    }
+}
+
+/**
+随机，使用 xorshift 生成伪随机数
+*/
+pub struct Random {
+    state: AtomicUsize,
+}
+
+impl Random {
+    /**
+    seed 不能为 0
+    */
+    pub fn new(seed: usize) -> Self {
+        assert!(seed != 0, "Seed of random replacement cannot be zero.");
+
+        Self { state: AtomicUsize::new(seed) }
+    }
+}
+
+impl Replace for Random {
+    fn _replace(&self, _line: &mut [Line], range: Range<usize>) -> usize {
+        let mut x = self.state.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state.store(x, Ordering::Relaxed);
+
+        range.start + x % range.len()
     }
 }
@@ -0,0 +1,362 @@
+/*!
+内核堆
+
+- 小对象按 2 的幂划分大小类，每类维护空闲块链表，空闲块的第一个字指向下一个空闲块
+- 大对象直接分配连续页框
+- 空闲块不足时从页框分配器取得新页，按需增长
+
+页框分配器自身也会使用堆，此时页框分配器已被当前 hart 加锁，堆只能使用预留的空闲页；其他 hart 持有页框分配器时则等待，平台应以 Frame::hart_set 提供当前 hart 的 id；
+因此堆在用尽预留页后会立即补充，并且平台应在初始化页框分配器之前用 extend 提供初始内存
+
+```no_run
+use ones::memory::heap::Heap;
+
+#[global_allocator]
+static HEAP: Heap = Heap::new();
+```
+
+# 结构体
+Heap
+
+Stat
+*/
+use core::alloc::{ GlobalAlloc, Layout };
+use core::ptr::null_mut;
+use spin::Mutex;
+
+use crate::memory::{ Address, page::frame::Frame };
+
+pub struct Heap {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// 各大小类空闲块链表的表头，0 表示链表为空
+    free: [usize; config::CLASS],
+    /// 尚未划分的空闲页链表的表头
+    spare: usize,
+    stat: Stat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stat {
+    /// 已分配的字节数，按大小类或页取整
+    pub allocated: usize,
+    /// allocated 的历史最大值
+    pub peak: usize,
+    /// 尚未释放的分配数
+    pub active: usize,
+    /// 从页框分配器取得的页框数
+    pub frame: usize,
+}
+
+impl Heap {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                free: [0; config::CLASS],
+                spare: 0,
+                stat: Stat { allocated: 0, peak: 0, active: 0, frame: 0 },
+            }),
+        }
+    }
+    /**
+    将内存 \[start, start + size) 中对齐的整页加入堆
+
+    # Safety
+    该内存此后归堆所有
+    */
+    pub unsafe fn extend(&self, start: usize, size: usize) {
+        let mut inner = self.inner.lock();
+        let page = Address::address(1);
+
+        let mut current = Address::address(Address::ceil(start));
+        while current + page <= start + size {
+            inner.spare_push(current);
+            current += page;
+        }
+    }
+
+    pub fn stat(&self) -> Stat {
+        self.inner.lock().stat
+    }
+    /**
+    小对象的大小类，大对象返回 None
+    */
+    fn class(layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align()).max(config::MIN).next_power_of_two();
+        if size > config::MAX {
+            return None;
+        }
+
+        Some((size.trailing_zeros() - config::MIN.trailing_zeros()) as usize)
+    }
+    /**
+    大对象占用的页数，对齐要求超过一页时按对齐取整
+    */
+    fn pages(layout: &Layout) -> usize {
+        Address::ceil(layout.size().max(layout.align()))
+    }
+    /**
+    从页框分配器取得一页放入空闲页链表
+    */
+    fn grow(&self) -> bool {
+        match Frame::try_new_nowait() {
+            Some(frame) => {
+                let address = Address::address(frame.number);
+                core::mem::forget(frame);
+
+                let mut inner = self.inner.lock();
+                inner.stat.frame += 1;
+                inner.spare_push(address);
+
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn alloc_small(&self, class: usize) -> *mut u8 {
+        loop {
+            let mut inner = self.inner.lock();
+            if let Some(block) = inner.pop(class) {
+                inner.stat_alloc(config::MIN << class);
+                let refill = inner.spare == 0;
+                drop(inner);
+
+                // keep a spare page for allocations made inside the frame allocator
+                if refill {
+                    self.grow();
+                }
+                return block as *mut u8;
+            }
+
+            if let Some(page) = inner.spare_pop() {
+                inner.carve(class, page);
+                continue;
+            }
+            drop(inner);
+
+            if !self.grow() {
+                return null_mut();
+            }
+        }
+    }
+
+    fn alloc_large(&self, layout: &Layout) -> *mut u8 {
+        let pages = Self::pages(layout);
+        let address = match Frame::try_contig_nowait(pages) {
+            Some(number) => Address::address(number),
+            None => return null_mut(),
+        };
+
+        let mut inner = self.inner.lock();
+        inner.stat.frame += pages;
+        inner.stat_alloc(Address::address(pages));
+
+        address as *mut u8
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match Self::class(&layout) {
+            Some(class) => self.alloc_small(class),
+            None => self.alloc_large(&layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match Self::class(&layout) {
+            Some(class) => {
+                let freed = {
+                    let mut inner = self.inner.lock();
+                    let freed = inner.push(class, ptr as usize);
+                    if freed {
+                        inner.stat_dealloc(config::MIN << class);
+                    }
+
+                    freed
+                };
+                assert!(freed, "Double free of {:#x}.", ptr as usize);
+            },
+            None => {
+                let pages = Self::pages(&layout);
+                {
+                    let mut inner = self.inner.lock();
+                    inner.stat.frame -= pages;
+                    inner.stat_dealloc(Address::address(pages));
+                }
+
+                let number = Address::number(ptr as usize);
+                for i in 0..pages {
+                    drop(Frame { number: number + i });
+                }
+            },
+        }
+    }
+}
+
+impl Inner {
+    fn pop(&mut self, class: usize) -> Option<usize> {
+        let block = self.free[class];
+        if block == 0 {
+            return None;
+        }
+
+        let word = block as *mut usize;
+        unsafe {
+            self.free[class] = *word;
+            if cfg!(debug_assertions) {
+                *word.add(1) = 0;
+            }
+        }
+
+        Some(block)
+    }
+    /**
+    将块放回空闲链表
+
+    调试构建中空闲块的第二个字写入 MAGIC，再次释放时据此检查，返回 false 表示重复释放
+    */
+    fn push(&mut self, class: usize, block: usize) -> bool {
+        let word = block as *mut usize;
+        unsafe {
+            if cfg!(debug_assertions) {
+                if *word.add(1) == config::MAGIC && self.contains(class, block) {
+                    return false;
+                }
+                *word.add(1) = config::MAGIC;
+            }
+            *word = self.free[class];
+        }
+        self.free[class] = block;
+
+        true
+    }
+
+    fn contains(&self, class: usize, block: usize) -> bool {
+        let mut current = self.free[class];
+        while current != 0 {
+            if current == block {
+                return true;
+            }
+            current = unsafe { *(current as *const usize) };
+        }
+
+        false
+    }
+    /**
+    将一页划分为大小类 class 的块
+    */
+    fn carve(&mut self, class: usize, page: usize) {
+        let size = config::MIN << class;
+        for block in (page..page + Address::address(1)).step_by(size).rev() {
+            let word = block as *mut usize;
+            unsafe {
+                *word = self.free[class];
+                if cfg!(debug_assertions) {
+                    *word.add(1) = config::MAGIC;
+                }
+            }
+            self.free[class] = block;
+        }
+    }
+
+    fn spare_push(&mut self, page: usize) {
+        unsafe { *(page as *mut usize) = self.spare; }
+        self.spare = page;
+    }
+
+    fn spare_pop(&mut self) -> Option<usize> {
+        if self.spare == 0 {
+            return None;
+        }
+
+        let page = self.spare;
+        self.spare = unsafe { *(page as *const usize) };
+
+        Some(page)
+    }
+
+    fn stat_alloc(&mut self, size: usize) {
+        self.stat.allocated += size;
+        self.stat.peak = self.stat.peak.max(self.stat.allocated);
+        self.stat.active += 1;
+    }
+
+    fn stat_dealloc(&mut self, size: usize) {
+        self.stat.allocated -= size;
+        self.stat.active -= 1;
+    }
+}
+
+mod config {
+    /// 最小的块，容纳链表指针与调试标记
+    pub const MIN: usize = 16;
+    /// 最大的块，更大的对象直接分配页框
+    pub const MAX: usize = 2048;
+    /// 大小类的数量
+    pub const CLASS: usize = 8;
+    /// 调试构建中空闲块的标记
+    pub const MAGIC: usize = 0xdead_beef_f4ee_b10c;
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use core::alloc::{ GlobalAlloc, Layout };
+    use super::{ Heap, Stat };
+
+    fn heap(pages: usize) -> Heap {
+        let heap = Heap::new();
+        let memory: &'static mut [u8] = vec![0u8; (pages + 1) * 4096].leak();
+        unsafe { heap.extend(memory.as_ptr() as usize, memory.len()) };
+
+        heap
+    }
+
+    #[test]
+    fn class() {
+        let heap = heap(3);
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let aligned = Layout::from_size_align(8, 256).unwrap();
+
+        unsafe {
+            let a = heap.alloc(small);
+            let b = heap.alloc(small);
+            let c = heap.alloc(aligned);
+            assert_eq!(b as usize - a as usize, 32);
+            assert_eq!(c as usize % 256, 0);
+            assert_eq!(heap.stat(), Stat { allocated: 32 + 32 + 256, peak: 320, active: 3, frame: 0 });
+
+            heap.dealloc(a, small);
+            // the freed block is reused first
+            assert_eq!(heap.alloc(small), a);
+            heap.dealloc(a, small);
+            heap.dealloc(b, small);
+            heap.dealloc(c, aligned);
+        }
+
+        assert_eq!(heap.stat(), Stat { allocated: 0, peak: 320, active: 0, frame: 0 });
+    }
+
+    #[test]
+    #[should_panic]
+    fn twice() {
+        let heap = heap(2);
+        let layout = Layout::from_size_align(100, 4).unwrap();
+
+        unsafe {
+            let a = heap.alloc(layout);
+            heap.dealloc(a, layout);
+            heap.dealloc(a, layout);
+        }
+    }
+}
@@ -12,7 +12,12 @@
 */
 
 pub mod page;
-// pub mod cache;
+pub mod cache;
+pub mod heap;
+pub mod address;
+
+pub use address::{ VirtAddr, PhysAddr, VirtPageNum, PhysPageNum, PageRange };
+use page::format::{ Format, Selected };
 
 use bitflags::bitflags;
 bitflags! {
@@ -41,9 +46,19 @@ impl Flag {
     pub fn is_valid(&self) -> bool {
         (self.bits & Self::V.bits) != Self::empty().bits
     }
+    /**
+    有效且可读、写或执行的页表项为叶项，否则指向下一级页表
+    */
+    #[inline]
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid() && self.intersects(Self::R | Self::W | Self::X)
+    }
 }
 
-pub type Address = ModelAddress<0xf_fff_fff_fff_fff_000, 0xfff>;
+/**
+页的大小由选定的页表格式决定；页号取页内偏移以上的所有位，使符号扩展的虚拟地址与页号可以互相转换，地址宽度由 VirtAddr 等检查
+*/
+pub type Address = ModelAddress<{ !<Selected as Format>::OFFSET_MASK }, { <Selected as Format>::OFFSET_MASK }>;
 
 /**
 假设虚拟地址仅由两部分组成：
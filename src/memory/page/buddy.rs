@@ -0,0 +1,244 @@
+/*!
+伙伴系统页框分配器
+
+阶为 order 的块包含 2^order 个页框，起始页框号按块大小对齐
+
+# 结构体
+Buddy
+
+Zone
+
+Stat
+*/
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+/**
+按区域管理页框，分配时依次尝试各区域
+*/
+pub struct Buddy {
+    pub zone: Vec<Zone>,
+}
+
+/**
+页框号范围 \[start, end] 内的伙伴系统
+*/
+pub struct Zone {
+    pub start: usize,
+    pub end: usize,
+    /// 各阶的空闲块，元素为块的起始页框号
+    free: [BTreeSet<usize>; config::ORDER],
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stat {
+    /// 页框总数
+    pub total: usize,
+    /// 空闲页框数
+    pub free: usize,
+    /// 各阶空闲块数
+    pub block: [usize; config::ORDER],
+}
+
+impl Buddy {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { zone: alloc::vec![Zone::new(start, end)] }
+    }
+    /**
+    添加区域，返回区域编号
+    */
+    pub fn zone_add(&mut self, start: usize, end: usize) -> usize {
+        self.zone.push(Zone::new(start, end));
+        self.zone.len() - 1
+    }
+    /**
+    分配 2^order 个连续页框，返回起始页框号
+    */
+    pub fn alloc(&mut self, order: usize) -> Option<usize> {
+        self.zone.iter_mut()
+            .find_map(|zone| zone.alloc(order))
+    }
+    /**
+    在指定区域中分配，如 DMA 区域
+    */
+    pub fn alloc_in(&mut self, zone: usize, order: usize) -> Option<usize> {
+        self.zone.get_mut(zone)?.alloc(order)
+    }
+    /**
+    分配 n 个连续页框，多余的页框立即释放；n 为 0 时返回 None
+    */
+    pub fn alloc_contig(&mut self, n: usize) -> Option<usize> {
+        if n == 0 {
+            return None;
+        }
+
+        let order = order(n);
+        let base = self.alloc(order)?;
+        for number in (base + n)..(base + (1 << order)) {
+            self.dealloc(number, 0);
+        }
+
+        Some(base)
+    }
+
+    pub fn dealloc(&mut self, number: usize, order: usize) {
+        self.zone.iter_mut()
+            .find(|zone| zone.start <= number && number <= zone.end)
+            .expect("Frame does not belong to any zone.")
+            .dealloc(number, order);
+    }
+
+    pub fn stat(&self) -> Stat {
+        let mut stat = Stat::default();
+        for zone in self.zone.iter() {
+            let current = zone.stat();
+            stat.total += current.total;
+            stat.free += current.free;
+            for (block, count) in stat.block.iter_mut().zip(current.block) {
+                *block += count;
+            }
+        }
+
+        stat
+    }
+}
+
+impl Zone {
+    pub fn new(start: usize, end: usize) -> Self {
+        assert!(start <= end, "Start frame number cannot be greater than end frame number");
+
+        let mut zone = Self {
+            start,
+            end,
+            free: Default::default(),
+        };
+
+        // split the range into the largest aligned blocks
+        let mut current = start;
+        while current <= end {
+            let mut order = (current.trailing_zeros() as usize).min(config::ORDER - 1);
+            while current + (1 << order) - 1 > end {
+                order -= 1;
+            }
+            zone.free[order].insert(current);
+            current += 1 << order;
+        }
+
+        zone
+    }
+
+    pub fn alloc(&mut self, order: usize) -> Option<usize> {
+        if order >= config::ORDER {
+            return None;
+        }
+
+        let current = (order..config::ORDER).find(|&i| !self.free[i].is_empty())?;
+        let base = self.free[current].pop_first().unwrap();
+        for i in (order..current).rev() {
+            self.free[i].insert(base + (1 << i));
+        }
+
+        Some(base)
+    }
+    /**
+    释放块并与空闲的伙伴合并
+    */
+    pub fn dealloc(&mut self, number: usize, order: usize) {
+        // the block lies in a free block, or contains a free smaller one
+        let free = (order..config::ORDER).any(|i| self.free[i].contains(&(number & !((1 << i) - 1))))
+            || (0..order).any(|i| self.free[i].range(number..number + (1 << order)).next().is_some());
+        assert!(!free, "Frame {:#x} is freed twice.", number);
+
+        let mut number = number;
+        let mut order = order;
+        while order < config::ORDER - 1 {
+            let buddy = number ^ (1 << order);
+            if !self.free[order].remove(&buddy) {
+                break;
+            }
+            number = number.min(buddy);
+            order += 1;
+        }
+
+        self.free[order].insert(number);
+    }
+
+    pub fn stat(&self) -> Stat {
+        let mut stat = Stat {
+            total: self.end - self.start + 1,
+            ..Default::default()
+        };
+        for (i, free) in self.free.iter().enumerate() {
+            stat.block[i] = free.len();
+            stat.free += free.len() << i;
+        }
+
+        stat
+    }
+}
+/**
+容纳 n 个页框的最小阶
+*/
+#[inline]
+pub fn order(n: usize) -> usize {
+    n.next_power_of_two().trailing_zeros() as usize
+}
+
+pub mod config {
+    /// 阶的数量，最大的块为 2^(ORDER - 1) 个页框
+    pub const ORDER: usize = 11;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ Buddy, order };
+
+    #[test]
+    fn buddy() {
+        // 0x101..=0x1ff, blocks of order 0, 1, ..., 7
+        let mut buddy = Buddy::new(0x101, 0x1ff);
+        assert_eq!(buddy.stat().free, 0xff);
+        assert_eq!(buddy.stat().block[..8], [1; 8]);
+
+        assert_eq!(buddy.alloc(2), Some(0x104));
+        let base = buddy.alloc_contig(3).unwrap();
+        assert_eq!(base % 4, 0);
+        assert_eq!(buddy.stat().free, 0xff - 4 - 3);
+        assert_eq!(buddy.alloc(8), None);
+        assert_eq!(buddy.alloc_contig(0), None);
+
+        // freeing every page separately coalesces the blocks again
+        buddy.dealloc(0x104, 2);
+        for number in base..base + 3 {
+            buddy.dealloc(number, 0);
+        }
+        assert_eq!(buddy.stat().block[..8], [1; 8]);
+
+        let zone = buddy.zone_add(0x400, 0x7ff);
+        assert_eq!(buddy.alloc(8), Some(0x400));
+        assert_eq!(buddy.alloc_in(zone, 10), None);
+        assert_eq!(buddy.stat().total, 0xff + 0x400);
+
+        assert_eq!(order(1), 0);
+        assert_eq!(order(5), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn twice() {
+        let mut buddy = Buddy::new(0, 7);
+        let number = buddy.alloc(0).unwrap();
+        buddy.dealloc(number, 0);
+        buddy.dealloc(number, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn overlap() {
+        let mut buddy = Buddy::new(0, 7);
+        let number = buddy.alloc(2).unwrap();
+        // a page of the block is freed on its own before the whole block
+        buddy.dealloc(number + 1, 0);
+        buddy.dealloc(number, 2);
+    }
+}
@@ -0,0 +1,452 @@
+/*!
+页表格式
+
+内置的页表格式，均为 4 KiB 页、每级 512 项，直接实现 Hal 与 Lib：
+
+```
+use ones::memory::{ Flag, VirtPageNum, page::{ Table, Lib, format::Sv39 } };
+
+fn map(table: &mut Table, page: VirtPageNum) {
+    Sv39::map(table, page, Flag::R | Flag::W);
+}
+```
+
+地址的宽度由选定的格式 Selected 决定，默认为 Sv39，可由 feature sv48、sv57、x86-64、aarch64-4k 之一选择，同时启用多个时编译失败
+
+Flag 按 RISC-V 的语义定义，其余格式的转换：
+- 无法表示不可读的页，叶项解码时总带有 R
+- 叶项带有软件位 LEAF，用于在不知道级数时区分叶项与中间页表项
+- 中间页表项不限制权限，权限由叶项决定
+- AArch64 的有效叶项总是置 AF，否则访问时产生 Access Flag 异常，因此解码时总带有 A
+
+# 结构体
+RiscV：Sv39、Sv48、Sv57
+
+X86：X86_64（4 级）
+
+AArch64：AArch64_4K（4 级，48 位虚拟地址）
+*/
+use alloc::vec::Vec;
+
+use crate::memory::Flag;
+use super::{ Hal, Lib, entry::Entry };
+
+/**
+页表格式的地址宽度
+*/
+pub trait Format: Hal {
+    /// 虚拟地址的有效位数
+    const VIRT_WIDTH: usize;
+    /// 物理地址的有效位数
+    const PHYS_WIDTH: usize;
+    /// 页内偏移的掩码
+    const OFFSET_MASK: usize = 0xfff;
+    /// 虚拟地址中页号的掩码
+    const NUMBER_MASK: usize = ((1 << Self::VIRT_WIDTH) - 1) & !Self::OFFSET_MASK;
+}
+
+#[cfg(any(
+    all(feature = "sv48", any(feature = "sv57", feature = "x86-64", feature = "aarch64-4k")),
+    all(feature = "sv57", any(feature = "x86-64", feature = "aarch64-4k")),
+    all(feature = "x86-64", feature = "aarch64-4k"),
+))]
+compile_error!("At most one of the features sv48, sv57, x86-64 and aarch64-4k can be enabled to select the page table format.");
+
+#[cfg(not(any(feature = "sv48", feature = "sv57", feature = "x86-64", feature = "aarch64-4k")))]
+pub type Selected = Sv39;
+#[cfg(feature = "sv48")]
+pub type Selected = Sv48;
+// with conflicting features only compile_error! is reported
+#[cfg(all(feature = "sv57", not(feature = "sv48")))]
+pub type Selected = Sv57;
+#[cfg(all(feature = "x86-64", not(any(feature = "sv48", feature = "sv57"))))]
+pub type Selected = X86_64;
+#[cfg(all(feature = "aarch64-4k", not(any(feature = "sv48", feature = "sv57", feature = "x86-64"))))]
+pub type Selected = AArch64_4K;
+
+/**
+RISC-V 的页表，LEVEL 为 3、4、5 时分别为 Sv39、Sv48、Sv57
+
+页表项的低 8 位与 Flag 一致，页框号位于 \[10, 54)
+*/
+pub struct RiscV<const LEVEL: usize>;
+
+pub type Sv39 = RiscV<3>;
+pub type Sv48 = RiscV<4>;
+pub type Sv57 = RiscV<5>;
+
+impl<const LEVEL: usize> Format for RiscV<LEVEL> {
+    const VIRT_WIDTH: usize = 12 + 9 * LEVEL;
+    const PHYS_WIDTH: usize = 56;
+}
+
+impl<const LEVEL: usize> Hal for RiscV<LEVEL> {
+    #[inline]
+    fn index(page_num: usize) -> Vec<usize> {
+        index(page_num, LEVEL)
+    }
+
+    #[inline]
+    fn conf() -> usize {
+        LEVEL
+    }
+
+    #[inline]
+    fn flag(entry: &Entry) -> Flag {
+        Flag::from_bits_truncate(entry.bits() as u8)
+    }
+
+    #[inline]
+    fn set_flag(entry: &mut Entry, page_flag: Flag) {
+        entry.bits_set((entry.bits() & !0xff) | page_flag.bits() as usize);
+    }
+
+    #[inline]
+    fn new_entry(frame_num: usize, page_flag: Flag) -> Entry {
+        Entry::from_bits(((frame_num & mask(44)) << 10) | page_flag.bits() as usize)
+    }
+
+    #[inline]
+    fn frame_number(entry: &Entry) -> usize {
+        (entry.bits() >> 10) & mask(44)
+    }
+}
+
+impl<const LEVEL: usize> Lib for RiscV<LEVEL> {}
+
+/**
+x86-64 的页表，LEVEL 为 4 或 5（LA57）
+
+仅倒数第 2、3 级支持大页（2 MiB、1 GiB），大页置 PS 位
+*/
+pub struct X86<const LEVEL: usize>;
+
+pub type X86_64 = X86<4>;
+
+mod x86 {
+    pub const P: usize = 1;
+    pub const RW: usize = 1 << 1;
+    pub const US: usize = 1 << 2;
+    pub const A: usize = 1 << 5;
+    pub const D: usize = 1 << 6;
+    pub const PS: usize = 1 << 7;
+    pub const G: usize = 1 << 8;
+    /// 软件位
+    pub const LEAF: usize = 1 << 9;
+    pub const NX: usize = 1 << 63;
+}
+
+impl<const LEVEL: usize> Format for X86<LEVEL> {
+    const VIRT_WIDTH: usize = 12 + 9 * LEVEL;
+    const PHYS_WIDTH: usize = 52;
+}
+
+impl<const LEVEL: usize> Hal for X86<LEVEL> {
+    #[inline]
+    fn index(page_num: usize) -> Vec<usize> {
+        index(page_num, LEVEL)
+    }
+
+    #[inline]
+    fn conf() -> usize {
+        LEVEL
+    }
+
+    #[inline]
+    fn huge(level: usize) -> bool {
+        level + 3 >= LEVEL
+    }
+
+    fn flag(entry: &Entry) -> Flag {
+        use x86::*;
+
+        let bits = entry.bits();
+        let mut flag = Flag::empty();
+        flag.set(Flag::V, bits & P != 0);
+        if bits & LEAF == 0 {
+            return flag;
+        }
+
+        flag |= Flag::R;
+        flag.set(Flag::W, bits & RW != 0);
+        flag.set(Flag::X, bits & NX == 0);
+        flag.set(Flag::U, bits & US != 0);
+        flag.set(Flag::G, bits & G != 0);
+        flag.set(Flag::A, bits & A != 0);
+        flag.set(Flag::D, bits & D != 0);
+
+        flag
+    }
+
+    fn set_flag(entry: &mut Entry, page_flag: Flag) {
+        let huge = entry.bits() & x86::PS;
+        let mut bits = Self::new_entry(Self::frame_number(entry), page_flag).bits();
+        if bits & x86::LEAF != 0 {
+            bits |= huge;
+        }
+
+        entry.bits_set(bits);
+    }
+
+    fn new_entry(frame_num: usize, page_flag: Flag) -> Entry {
+        use x86::*;
+
+        let mut bits = (frame_num & mask(40)) << 12;
+        if page_flag.is_valid() {
+            bits |= P;
+        }
+        if !page_flag.intersects(Flag::R | Flag::W | Flag::X) {
+            // the next level decides the permission
+            if page_flag.is_valid() {
+                bits |= RW | US;
+            }
+            return Entry::from_bits(bits);
+        }
+
+        bits |= LEAF;
+        if page_flag.contains(Flag::W) { bits |= RW; }
+        if !page_flag.contains(Flag::X) { bits |= NX; }
+        if page_flag.contains(Flag::U) { bits |= US; }
+        if page_flag.contains(Flag::G) { bits |= G; }
+        if page_flag.contains(Flag::A) { bits |= A; }
+        if page_flag.contains(Flag::D) { bits |= D; }
+
+        Entry::from_bits(bits)
+    }
+
+    fn new_leaf(frame_num: usize, page_flag: Flag, level: usize) -> Entry {
+        let entry = Self::new_entry(frame_num, page_flag);
+        if level + 1 < LEVEL {
+            Entry::from_bits(entry.bits() | x86::PS)
+        } else {
+            entry
+        }
+    }
+
+    #[inline]
+    fn frame_number(entry: &Entry) -> usize {
+        (entry.bits() >> 12) & mask(40)
+    }
+}
+
+impl<const LEVEL: usize> Lib for X86<LEVEL> {}
+
+/**
+AArch64 4 KiB 粒度的 stage 1 页表，LEVEL 为 3（39 位虚拟地址）或 4（48 位虚拟地址）
+
+仅倒数第 2、3 级支持块映射（2 MiB、1 GiB）；叶项使用 MAIR 的第 0 项并设为内部共享，
+D 位以软件位 DIRTY 记录
+*/
+pub struct AArch64<const LEVEL: usize>;
+
+pub type AArch64_4K = AArch64<4>;
+
+mod aarch64 {
+    pub const VALID: usize = 1;
+    /// 中间页表项与末级页表项置位，块映射清除
+    pub const TABLE: usize = 1 << 1;
+    /// EL0 可访问
+    pub const AP_EL0: usize = 1 << 6;
+    /// 只读
+    pub const AP_RO: usize = 1 << 7;
+    /// 内部共享
+    pub const SH_INNER: usize = 0b11 << 8;
+    pub const AF: usize = 1 << 10;
+    pub const NG: usize = 1 << 11;
+    pub const PXN: usize = 1 << 53;
+    pub const UXN: usize = 1 << 54;
+    /// 软件位
+    pub const LEAF: usize = 1 << 55;
+    /// 软件位
+    pub const DIRTY: usize = 1 << 56;
+}
+
+impl<const LEVEL: usize> Format for AArch64<LEVEL> {
+    const VIRT_WIDTH: usize = 12 + 9 * LEVEL;
+    const PHYS_WIDTH: usize = 48;
+}
+
+impl<const LEVEL: usize> Hal for AArch64<LEVEL> {
+    #[inline]
+    fn index(page_num: usize) -> Vec<usize> {
+        index(page_num, LEVEL)
+    }
+
+    #[inline]
+    fn conf() -> usize {
+        LEVEL
+    }
+
+    #[inline]
+    fn huge(level: usize) -> bool {
+        level + 3 >= LEVEL
+    }
+
+    fn flag(entry: &Entry) -> Flag {
+        use aarch64::*;
+
+        let bits = entry.bits();
+        let mut flag = Flag::empty();
+        flag.set(Flag::V, bits & VALID != 0);
+        if bits & LEAF == 0 {
+            return flag;
+        }
+
+        let user = bits & AP_EL0 != 0;
+        flag |= Flag::R;
+        flag.set(Flag::W, bits & AP_RO == 0);
+        flag.set(Flag::X, bits & if user { UXN } else { PXN } == 0);
+        flag.set(Flag::U, user);
+        flag.set(Flag::G, bits & NG == 0);
+        flag.set(Flag::A, bits & AF != 0);
+        flag.set(Flag::D, bits & DIRTY != 0);
+
+        flag
+    }
+
+    fn set_flag(entry: &mut Entry, page_flag: Flag) {
+        use aarch64::*;
+
+        let block = entry.bits() & (LEAF | TABLE) == LEAF;
+        let mut bits = Self::new_entry(Self::frame_number(entry), page_flag).bits();
+        if block && bits & LEAF != 0 {
+            bits &= !TABLE;
+        }
+
+        entry.bits_set(bits);
+    }
+
+    fn new_entry(frame_num: usize, page_flag: Flag) -> Entry {
+        use aarch64::*;
+
+        let mut bits = (frame_num & mask(36)) << 12;
+        if page_flag.is_valid() {
+            bits |= VALID;
+        }
+        if !page_flag.intersects(Flag::R | Flag::W | Flag::X) {
+            if page_flag.is_valid() {
+                bits |= TABLE;
+            }
+            return Entry::from_bits(bits);
+        }
+
+        // AF is always set, a valid leaf without it faults on every access
+        bits |= LEAF | TABLE | SH_INNER | AF;
+        // execute permission of the other exception level is always removed
+        bits |= match (page_flag.contains(Flag::X), page_flag.contains(Flag::U)) {
+            (true, true) => AP_EL0 | PXN,
+            (true, false) => UXN,
+            (false, true) => AP_EL0 | PXN | UXN,
+            (false, false) => PXN | UXN,
+        };
+        if !page_flag.contains(Flag::W) { bits |= AP_RO; }
+        if !page_flag.contains(Flag::G) { bits |= NG; }
+        if page_flag.contains(Flag::D) { bits |= DIRTY; }
+
+        Entry::from_bits(bits)
+    }
+
+    fn new_leaf(frame_num: usize, page_flag: Flag, level: usize) -> Entry {
+        let entry = Self::new_entry(frame_num, page_flag);
+        if level + 1 < LEVEL && entry.bits() & aarch64::LEAF != 0 {
+            Entry::from_bits(entry.bits() & !aarch64::TABLE)
+        } else {
+            entry
+        }
+    }
+
+    #[inline]
+    fn frame_number(entry: &Entry) -> usize {
+        (entry.bits() >> 12) & mask(36)
+    }
+}
+
+impl<const LEVEL: usize> Lib for AArch64<LEVEL> {}
+
+/**
+各级页表的索引，根页表在前
+*/
+fn index(page_num: usize, level: usize) -> Vec<usize> {
+    (0..level).rev()
+        .map(|i| (page_num >> (9 * i)) & 0x1ff)
+        .collect()
+}
+
+#[inline]
+fn mask(width: usize) -> usize {
+    (1 << width) - 1
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use crate::memory::{ Flag, Address };
+    use super::{ Format, Hal, Sv39, Sv48, Sv57, X86_64, AArch64_4K };
+
+    #[test]
+    fn riscv() {
+        let page = Address::number(0x8020_1000);
+        assert_eq!(Sv39::index(page), vec![2, 1, 1]);
+        assert_eq!(Sv48::index(page), vec![0, 2, 1, 1]);
+        assert_eq!(Sv57::index(page).len(), 5);
+        assert_eq!((Sv39::VIRT_WIDTH, Sv48::VIRT_WIDTH, Sv57::VIRT_WIDTH), (39, 48, 57));
+        assert_eq!(Sv39::NUMBER_MASK, 0x7f_ffff_f000);
+
+        let flag = Flag::V | Flag::R | Flag::W | Flag::A | Flag::D;
+        let entry = Sv39::new_entry(0x80201, flag);
+        assert_eq!(entry.bits(), 0x2008_04c7);
+        assert_eq!((Sv39::frame_number(&entry), Sv39::flag(&entry)), (0x80201, flag));
+        assert_eq!(Sv39::new_entry(0x80202, Flag::V).bits(), 0x2008_0801);
+    }
+
+    #[test]
+    fn x86() {
+        // the higher half starts at the 256th PML4 entry
+        assert_eq!(X86_64::index(Address::number(0xffff_8000_0020_0000)), vec![256, 0, 1, 0]);
+        assert!(!X86_64::huge(0) && X86_64::huge(1) && X86_64::huge(2));
+
+        assert_eq!(X86_64::new_entry(0x1234, Flag::V).bits(), 0x0123_4007);
+        assert_eq!(X86_64::flag(&X86_64::new_entry(0x1234, Flag::V)), Flag::V);
+
+        // kernel text in a 2 MiB page, user data in a 4 KiB page
+        let text = Flag::V | Flag::R | Flag::X | Flag::G | Flag::A;
+        let entry = X86_64::new_leaf(0x200, text, 2);
+        assert_eq!(entry.bits(), 0x0020_03a1);
+        assert_eq!(X86_64::flag(&entry), text);
+        let data = Flag::V | Flag::R | Flag::W | Flag::U | Flag::A | Flag::D;
+        let mut entry = X86_64::new_leaf(0x1234, data, 3);
+        assert_eq!(entry.bits(), 0x8000_0000_0123_4267);
+        assert_eq!((X86_64::frame_number(&entry), X86_64::flag(&entry)), (0x1234, data));
+
+        // a page cannot be unreadable
+        X86_64::set_flag(&mut entry, Flag::V | Flag::W);
+        assert_eq!(X86_64::flag(&entry), Flag::V | Flag::R | Flag::W);
+    }
+
+    #[test]
+    fn aarch64() {
+        assert_eq!(AArch64_4K::index(Address::number(0x4000_1000)), vec![0, 1, 0, 1]);
+        assert_eq!(AArch64_4K::VIRT_WIDTH, 48);
+
+        assert_eq!(AArch64_4K::new_entry(0x4_0000, Flag::V).bits(), 0x4000_0003);
+
+        // kernel data in a 2 MiB block
+        let data = Flag::V | Flag::R | Flag::W | Flag::G | Flag::A;
+        let mut entry = AArch64_4K::new_leaf(0x4_0000, data, 2);
+        assert_eq!(entry.bits(), 0x00e0_0000_4000_0701);
+        assert_eq!(AArch64_4K::flag(&entry), data);
+        // the block stays a block when its permission changes
+        AArch64_4K::set_flag(&mut entry, data - Flag::W);
+        assert_eq!(entry.bits(), 0x00e0_0000_4000_0781);
+
+        // user text in a 4 KiB page
+        let text = Flag::V | Flag::R | Flag::X | Flag::U | Flag::A;
+        let entry = AArch64_4K::new_leaf(0x4_0001, text, 3);
+        assert_eq!(entry.bits(), 0x00a0_0000_4000_1fc3);
+        assert_eq!((AArch64_4K::frame_number(&entry), AArch64_4K::flag(&entry)), (0x4_0001, text));
+
+        // AF is set even if A is not requested
+        let entry = AArch64_4K::new_leaf(0x4_0001, text - Flag::A, 3);
+        assert_eq!(entry.bits(), 0x00a0_0000_4000_1fc3);
+    }
+}
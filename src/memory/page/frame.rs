@@ -14,10 +14,10 @@ pub struct Frame {
 
 impl Drop for Frame {
     fn drop(&mut self) {
-        let mut allocator = ALLOCATOR.lock();
+        let mut allocator = lock();
         let allocaor = allocator.as_mut().unwrap();
         info!("Frame {} is deallocated.", self.number);
-        allocaor.dealloc(self.number)
+        allocaor.dealloc(self.number, 0)
     }
 }
 
@@ -27,47 +27,188 @@ impl Frame {
     */
     #[inline]
     pub fn new() -> Self {
-        let mut allocator = ALLOCATOR.lock();
+        Self::try_new().expect("Frame allocator over.")
+    }
+    /**
+    Allocate a new frame, return None if the allocator is exhausted.
+    */
+    #[inline]
+    pub fn try_new() -> Option<Self> {
+        let mut allocator = lock();
         let allocaor = allocator.as_mut().unwrap();
-        Self {
-            number: allocaor.alloc().unwrap()
-        }
+        allocaor.alloc(0).map(|number| Self { number })
+    }
+    /**
+    Allocate a new frame for the heap, return None if the frame allocator is re-entered on this hart.
+
+    Used by the heap, which may be called by the frame allocator itself. Other harts holding the allocator are waited for.
+    */
+    #[inline]
+    pub fn try_new_nowait() -> Option<Self> {
+        let mut allocator = reentrant_lock()?;
+        allocator.as_mut()?.alloc(0).map(|number| Self { number })
+    }
+    /**
+    Fill the frame with zero.
+    */
+    #[inline]
+    pub fn clear(&self) {
+        use crate::memory::Address;
+
+        let address = Address::address(self.number);
+        unsafe { core::ptr::write_bytes(address as *mut u8, 0, Address::address(1)) }
+    }
+    /**
+    Copy the content of source into the frame.
+    */
+    #[inline]
+    pub fn copy_from(&self, source: &Frame) {
+        use crate::memory::Address;
+
+        let from = Address::address(source.number) as *const u8;
+        let to = Address::address(self.number) as *mut u8;
+        unsafe { core::ptr::copy_nonoverlapping(from, to, Address::address(1)) }
     }
     /**
-    Allocate n contiguous frames, return.
+    Allocate n contiguous frames aligned to the next power of two, panic if it fails.
     */
     pub fn new_contig(n: usize) -> Vec<Self> {
-        let mut allocator = ALLOCATOR.lock();
-        let allocaor = allocator.as_mut().unwrap();
+        Self::try_new_contig(n).expect("Frame allocator over.")
+    }
+    /**
+    Allocate n contiguous frames, return None if no free block is large enough.
 
-        let base = allocaor.alloc_contig(n)
-        .expect("Frame allocator over.");
+    Each frame is freed on its own and coalesced by the buddy allocator.
+    */
+    pub fn try_new_contig(n: usize) -> Option<Vec<Self>> {
+        let base = {
+            let mut allocator = lock();
+            allocator.as_mut().unwrap().alloc_contig(n)?
+        };
 
-        let mut vecotr = Vec::new();
-        for i in 0..n {
-            vecotr.push(Frame { number: base + i });
-        }
+        // the vector is allocated after the allocator lock is released
+        Some((base..base + n).map(|number| Frame { number }).collect())
+    }
+    /**
+    Allocate n contiguous frames for the heap like try_new_nowait, return the first frame number.
 
-        vecotr
+    The caller frees each frame on its own.
+    */
+    pub fn try_contig_nowait(n: usize) -> Option<usize> {
+        let mut allocator = reentrant_lock()?;
+        allocator.as_mut()?.alloc_contig(n)
     }
     /**
-    Initialize the frame allocator.
+    Initialize the frame allocator with the zone \[head, tail].
     */
     #[inline]
     pub fn init(head: usize, tail: usize) {
-        let mut allocator = ALLOCATOR.lock();
-        if let None = *allocator {
-            *allocator = Some(Allocator::new(head, tail).unwrap())
+        let mut allocator = lock();
+        if allocator.is_none() {
+            *allocator = Some(Buddy::new(head, tail))
+        }
+    }
+    /**
+    Add the zone \[head, tail] to the frame allocator, return the zone index.
+
+    The allocator is initialized with the zone if it is not initialized yet.
+    */
+    pub fn zone_add(head: usize, tail: usize) -> usize {
+        let mut allocator = lock();
+        match allocator.as_mut() {
+            Some(allocator) => allocator.zone_add(head, tail),
+            None => {
+                *allocator = Some(Buddy::new(head, tail));
+                0
+            },
         }
     }
+    /**
+    Set the function returning the id of the current hart, used to detect re-entrance of the frame allocator.
+
+    Without it all harts are taken as hart 0, and the heap fails to grow while another hart holds the allocator.
+    */
+    pub fn hart_set(hart: fn() -> usize) {
+        HART.call_once(|| hart);
+    }
+    /**
+    Free frame statistics of all zones.
+    */
+    pub fn stat() -> Stat {
+        let allocator = lock();
+        allocator.as_ref().unwrap().stat()
+    }
 }
 
 use alloc::vec::Vec;
+use core::ops::{ Deref, DerefMut };
+use core::sync::atomic::{ AtomicUsize, Ordering };
 use log::info;
-use spin::Mutex;
+use spin::{ Mutex, MutexGuard, Once };
 use lazy_static::lazy_static;
-use crate::Allocator;
+use super::buddy::{ Buddy, Stat };
 
 lazy_static! {
-    static ref ALLOCATOR: Mutex<Option<Allocator>> = Mutex::new(None);
+    static ref ALLOCATOR: Mutex<Option<Buddy>> = Mutex::new(None);
+}
+static HART: Once<fn() -> usize> = Once::new();
+/// The hart holding ALLOCATOR, usize::MAX if none.
+static HOLDER: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+#[inline]
+fn hart() -> usize {
+    HART.get().map_or(0, |hart| hart())
+}
+/**
+The frame allocator locked by the current hart.
+*/
+struct Guard(MutexGuard<'static, Option<Buddy>>);
+
+impl Deref for Guard {
+    type Target = Option<Buddy>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Guard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        HOLDER.store(usize::MAX, Ordering::Release);
+    }
+}
+
+fn lock() -> Guard {
+    let guard = ALLOCATOR.lock();
+    HOLDER.store(hart(), Ordering::Release);
+    Guard(guard)
+}
+/**
+Lock the frame allocator unless the current hart already holds it, i.e. the heap is called by the frame allocator itself.
+*/
+fn reentrant_lock() -> Option<Guard> {
+    if HOLDER.load(Ordering::Acquire) == hart() {
+        return None;
+    }
+
+    Some(lock())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ lock, reentrant_lock };
+
+    #[test]
+    fn reentrant() {
+        let allocator = lock();
+        // the heap called by the frame allocator on the same hart does not wait
+        assert!(reentrant_lock().is_none());
+        drop(allocator);
+    }
 }
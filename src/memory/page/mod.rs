@@ -18,10 +18,19 @@ Table
 */
 pub mod frame;
 pub mod entry;
-
+pub mod swap;
+pub mod shm;
+pub mod buddy;
+pub mod format;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+
+use alloc::collections::{ BTreeMap, BTreeSet };
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use entry::Entry;
-use crate::memory::{ Flag, page::frame::Frame };
+use crate::memory::{ Flag, VirtAddr, PhysAddr, VirtPageNum, PhysPageNum, PageRange, page::frame::Frame };
 
 #[derive(Clone, Copy)]
 pub enum Map {
@@ -29,193 +38,332 @@ pub enum Map {
     Random,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// 页框分配器耗尽
+    OutOfMemory,
+    /// 页未被映射
+    NotMapped,
+    /// 页已被映射
+    AlreadyMapped,
+    /// 页的标志位不允许该访问
+    PermissionDenied,
+    /// 地址未对齐或对象跨越页边界
+    Misaligned,
+    /// 交换区已满
+    SwapFull,
+    /// 参数无效，如长度为零的映射
+    Invalid,
+    /// 访问了 stack 下方的保护页面
+    StackOverflow,
+    /// 字符串超出最大长度
+    TooLong,
+    /// 字符串不是合法的 UTF-8
+    Encoding,
+}
+
 pub trait Lib: Hal {
-    fn unmap(table: &mut Table, page_num: usize) {
-        let entry = Self::get_mut(table, page_num);
+    /**
+    解除映射
 
-        Self::set_flag(entry, Flag::empty());
+    释放页表持有的页框，并回收变为空的中间页表
+    */
+    fn unmap(table: &mut Table, page_num: VirtPageNum) {
+        let _ = Self::try_unmap(table, page_num);
     }
     /**
-    将页映射到给定的页框
-
-    # 安全性
-    root
-    frame_num
-    page_flag
+    解除映射，页未被映射时返回 Error::NotMapped
     */
-    fn map(table: &mut Table, page_num: usize, page_flag: Flag) {
-        let index = Self::index(page_num);
-        let root = Self::as_table(table.root.number);
+    fn try_unmap(table: &mut Table, page_num: VirtPageNum) -> Result<(), Error> {
+        let index = Self::index(page_num.as_usize());
+
+        // frame numbers of the tables along the walk
+        let mut path = Vec::with_capacity(Self::conf());
+        let mut current = table.root.number;
+        for (level, &i) in index.iter().take(Self::conf() - 1).enumerate() {
+            path.push(current);
+            let entry = &mut Self::as_table(current)[i];
+            let flag = Self::flag(entry);
+            if !flag.is_valid() {
+                return Err(Error::NotMapped);
+            }
+            if flag.is_leaf() {
+                Self::split(table, entry, level)?;
+            }
+            current = Self::frame_number(entry);
+        }
+        path.push(current);
 
-        let mut current_entry = &mut root[index[0]];
-        let mut current_table = if !Self::flag(current_entry).is_valid() {
-            let frame = Frame::new();
-            let frame_number = frame.number;
-            *current_entry = Self::new_entry(frame_number, Flag::V);
-            table.frame.push(frame);
+        // an invalid entry with permission bits is swapped out and unmapped as well
+        let leaf = &mut Self::as_table(current)[index[Self::conf() - 1]];
+        if Self::flag(leaf).is_empty() {
+            return Err(Error::NotMapped);
+        }
+        *leaf = Self::new_entry(0, Flag::empty());
+        table.frame.remove(&page_num);
+        table.cow.remove(&page_num);
+        table.shared.remove(&page_num);
+        swap::free(table, page_num);
+
+        for level in (1..Self::conf()).rev() {
+            let number = path[level];
+            if Self::as_table(number).iter().any(|entry| !Self::flag(entry).is_empty()) {
+                break;
+            }
 
-            Self::as_table(frame_number)
-        } else {
-            let frame_number = Self::frame_number(current_entry);
-            Self::as_table(frame_number)
-        };
+            Self::as_table(path[level - 1])[index[level - 1]] = Self::new_entry(0, Flag::empty());
+            table.table.remove(&number);
+        }
+
+        Ok(())
+    }
+    /**
+    解除页号范围内的映射
+    */
+    fn unmap_area(table: &mut Table, range: PageRange<VirtPageNum>) {
+        for page in range {
+            Self::unmap(table, page);
+        }
+    }
+    /**
+    返回页号对应的末级页表项，必要时创建中间页表
+    */
+    fn leaf(table: &mut Table, page_num: VirtPageNum) -> &'static mut Entry {
+        Self::try_leaf(table, page_num).expect("Frame allocator over.")
+    }
+    /**
+    同 leaf，创建中间页表时页框不足返回 Error::OutOfMemory
+    */
+    fn try_leaf(table: &mut Table, page_num: VirtPageNum) -> Result<&'static mut Entry, Error> {
+        Self::try_entry(table, page_num, Self::conf() - 1)
+    }
+    /**
+    返回页号在第 level 级（根页表为第 0 级）的页表项，必要时创建中间页表
 
-        for i in 1..(Self::conf() - 1) {
-            current_entry = &mut current_table[index[i]];
-            current_table = if !Self::flag(current_entry).is_valid() {
-                let frame = Frame::new();
+    路径上的大页被拆分为下一级的映射
+    */
+    fn try_entry(table: &mut Table, page_num: VirtPageNum, level: usize) -> Result<&'static mut Entry, Error> {
+        let index = Self::index(page_num.as_usize());
+
+        let mut current_table = Self::as_table(table.root.number);
+        for (current, &i) in index.iter().take(level).enumerate() {
+            let current_entry = &mut current_table[i];
+            let flag = Self::flag(current_entry);
+            current_table = if !flag.is_valid() {
+                let frame = Frame::try_new().ok_or(Error::OutOfMemory)?;
+                frame.clear();
                 let frame_number = frame.number;
                 *current_entry = Self::new_entry(frame_number, Flag::V);
-                table.frame.push(frame);
+                table.table.insert(frame_number, frame);
 
                 Self::as_table(frame_number)
             } else {
+                if flag.is_leaf() {
+                    Self::split(table, current_entry, current)?;
+                }
                 let frame_number = Self::frame_number(current_entry);
                 Self::as_table(frame_number)
-            }
+            };
         }
 
-        current_entry = &mut current_table[index[Self::conf() - 1]];
-        if !Self::flag(current_entry).is_valid() {
-            let frame = Frame::new();
-            *current_entry = Self::new_entry(frame.number, Flag::V | page_flag);
-            table.frame.push(frame);
-        }
+        Ok(&mut current_table[index[level]])
     }
-
-    fn map_area(table: &mut Table, page_num: (usize, usize), page_flag: Flag) {
-        let (start, end) = page_num;
-    
-        if start > end {
-            panic!("Start page number cannot be greater than end page number");
+    /**
+    将第 level 级的大页拆分为下一级的 512 个映射，标志位保持不变
+    */
+    fn split(table: &mut Table, entry: &mut Entry, level: usize) -> Result<(), Error> {
+        let frame = Frame::try_new().ok_or(Error::OutOfMemory)?;
+        let base = Self::frame_number(entry);
+        let flag = Self::flag(entry);
+        let span = Self::span(level + 1);
+
+        for (i, sub) in Self::as_table(frame.number).iter_mut().enumerate() {
+            *sub = Self::new_leaf(base + i * span, flag, level + 1);
         }
+        *entry = Self::new_entry(frame.number, Flag::V);
+        table.table.insert(frame.number, frame);
+
+        Ok(())
+    }
+    /**
+    第 level 级页表项映射的页数
+    */
+    #[inline]
+    fn span(level: usize) -> usize {
+        1 << (9 * (Self::conf() - 1 - level))
+    }
+    /**
+    将页映射到新分配的页框，该页框由页表持有
 
-        for i in 0..=(end - start) {
-            Self::map(table, start + i, page_flag);
+    页已被映射时不做任何操作
+    */
+    fn map(table: &mut Table, page_num: VirtPageNum, page_flag: Flag) {
+        match Self::try_map(table, page_num, page_flag) {
+            Ok(()) | Err(Error::AlreadyMapped) => {},
+            Err(error) => panic!("Map page {:?} failed: {:?}", page_num, error),
         }
     }
     /**
-    实现参考：
-    ```
-    fn fixed_map(table: &mut Table, page_num: usize, frame_num: usize, page_flag: Flag) {
-        let index = Self::index(page_num); let root = Self::as_table(table.root.number);
-
-        let mut current_entry = &mut root[index[0]];
-        let mut current_table = if !EntryLib::flag(current_entry).is_valid() {
-            let frame = Frame::new();
-            let frame_number = frame.number;
-            *current_entry = EntryLib::new(frame_number, Flag::V);
-            table.frame.push(frame);
-            Self::as_table(frame_number)
-        } else { 
-            let frame_number = EntryLib::frame_number(current_entry);
-            Self::as_table(frame_number)
-        };
+    将页映射到新分配的页框，页已被映射时返回 Error::AlreadyMapped
 
-        for i in 1..(Self::conf() - 1) {
-            current_entry = &mut current_table[index[i]]; 
-            current_table = if !EntryLib::flag(current_entry).is_valid() {
-                let frame = Frame::new();
-                let frame_number = frame.number;
-                *current_entry = EntryLib::new(frame_number, Flag::V);
-                table.frame.push(frame);
+    页框由 swap::alloc 分配，页框不足时可能换出 table 中的另一页
+    */
+    fn try_map(table: &mut Table, page_num: VirtPageNum, page_flag: Flag) -> Result<(), Error> {
+        // checked before allocating, which may evict another page
+        if !Self::flag(Self::try_leaf(table, page_num)?).is_empty() {
+            return Err(Error::AlreadyMapped);
+        }
 
-                Self::as_table(frame_number)
-            } else {
-                let frame_number = EntryLib::frame_number(current_entry);
+        let frame = swap::alloc::<Self>(table)?;
+        let current_entry = Self::try_leaf(table, page_num)?;
 
-                Self::as_table(frame_number)
-            }
-        }
+        frame.clear();
+        *current_entry = Self::new_entry(frame.number, Flag::V | page_flag);
+        table.frame.insert(page_num, Arc::new(frame));
 
-        current_entry = &mut current_table[index[Self::conf() - 1]];
-        *current_entry = EntryLib::new(frame_num, page_flag | Flag::V);
+        Ok(())
     }
-    ```
+    /**
+    将已有的页框 frame 映射到页号 page_num，页框可写地共享而不写时复制，如共享内存对象的页框
     */
-    fn fixed_map(table: &mut Table, page_num: usize, frame_num: usize, page_flag: Flag) {
-        let index = Self::index(page_num);
-        let root = Self::as_table(table.root.number);
-
-        let mut current_entry = &mut root[index[0]];
-        let mut current_table = if !Self::flag(current_entry).is_valid() {
-            let frame = Frame::new();
-            let frame_number = frame.number;
-            *current_entry = Self::new_entry(frame_number, Flag::V);
-            table.frame.push(frame);
-            Self::as_table(frame_number)
-        } else { 
-            let frame_number = Self::frame_number(current_entry);
-            Self::as_table(frame_number)
-        };
+    fn try_map_frame(table: &mut Table, page_num: VirtPageNum, frame: Arc<Frame>, page_flag: Flag) -> Result<(), Error> {
+        let current_entry = Self::try_leaf(table, page_num)?;
+        if !Self::flag(current_entry).is_empty() {
+            return Err(Error::AlreadyMapped);
+        }
 
-        for i in 1..(Self::conf() - 1) {
-            current_entry = &mut current_table[index[i]]; 
-            current_table = if !Self::flag(current_entry).is_valid() {
-                let frame = Frame::new();
-                let frame_number = frame.number;
-                *current_entry = Self::new_entry(frame_number, Flag::V);
-                table.frame.push(frame);
+        *current_entry = Self::new_entry(frame.number, Flag::V | page_flag);
+        table.frame.insert(page_num, frame);
+        table.shared.insert(page_num);
 
-                Self::as_table(frame_number)
-            } else {
-                let frame_number = Self::frame_number(current_entry);
+        Ok(())
+    }
 
-                Self::as_table(frame_number)
+    fn map_area(table: &mut Table, range: PageRange<VirtPageNum>, page_flag: Flag) {
+        for page in range {
+            Self::map(table, page, page_flag);
+        }
+    }
+    /**
+    映射页号范围，失败时撤销本次已建立的映射
+    */
+    fn try_map_area(table: &mut Table, range: PageRange<VirtPageNum>, page_flag: Flag) -> Result<(), Error> {
+        for page in range {
+            if let Err(error) = Self::try_map(table, page, page_flag) {
+                Self::unmap_area(table, PageRange::new(range.start, page));
+                return Err(error);
             }
         }
 
-        current_entry = &mut current_table[index[Self::conf() - 1]];
-        *current_entry = Self::new_entry(frame_num, page_flag | Flag::V);
+        Ok(())
+    }
+    /**
+    将页映射到给定的页框，该页框不由页表持有
+    */
+    fn fixed_map(table: &mut Table, page_num: VirtPageNum, frame_num: PhysPageNum, page_flag: Flag) {
+        let current_entry = Self::leaf(table, page_num);
+        *current_entry = Self::new_entry(frame_num.as_usize(), page_flag | Flag::V);
     } 
-    /**insert page number area \[start, end]
-     
+    /**
+    同 fixed_map，页已被映射时返回 Error::AlreadyMapped
     */
-    fn fixed_map_area(table: &mut Table, page: (usize, usize), frame: usize, flag: Flag) {
-        let (start, end) = page;
-
-        if start > end {
-            panic!("Start page number cannot be greater than end page number");
+    fn try_fixed_map(table: &mut Table, page_num: VirtPageNum, frame_num: PhysPageNum, page_flag: Flag) -> Result<(), Error> {
+        let current_entry = Self::try_leaf(table, page_num)?;
+        if !Self::flag(current_entry).is_empty() {
+            return Err(Error::AlreadyMapped);
         }
 
-        for i in 0..=(end - start) {
-            Self::fixed_map(table, start + i, frame + i, flag);
-        }
+        *current_entry = Self::new_entry(frame_num.as_usize(), page_flag | Flag::V);
+
+        Ok(())
     }
+    /**
+    在第 level 级建立大页映射，页号与页框号须按大页大小对齐
 
-    fn get(table: &mut Table, page_num: usize) -> (usize, Flag) {
-        let index = Self::index(page_num); let root = Self::as_table(table.root.number);
+    该位置已存在下一级页表时返回 Error::AlreadyMapped，已有的叶项被覆盖
+    */
+    fn try_fixed_map_huge(table: &mut Table, page_num: VirtPageNum, frame_num: PhysPageNum, page_flag: Flag, level: usize) -> Result<(), Error> {
+        let span = Self::span(level);
+        assert!(page_num.as_usize().is_multiple_of(span) && frame_num.as_usize().is_multiple_of(span), "Huge page is not aligned");
+
+        let current_entry = Self::try_entry(table, page_num, level)?;
+        let flag = Self::flag(current_entry);
+        if flag.is_valid() && !flag.is_leaf() {
+            return Err(Error::AlreadyMapped);
+        }
 
-        let mut current_entry = &mut root[index[0]];
-        let frame_number = Self::frame_number(current_entry);
-        let mut current_table = Self::as_table(frame_number);
+        *current_entry = Self::new_leaf(frame_num.as_usize(), page_flag | Flag::V, level);
 
-        for i in 1..(Self::conf() - 1) {
-            current_entry = &mut current_table[index[i]];
-            let frame_number = Self::frame_number(current_entry);
-            current_table = Self::as_table(frame_number);
+        Ok(())
+    }
+    /**insert page number area \[start, end)
+     
+    每次选取对齐且不超出范围的最大页，已存在下一级页表的位置退回较小的页
+    */
+    fn fixed_map_area(table: &mut Table, range: PageRange<VirtPageNum>, frame: PhysPageNum, flag: Flag) {
+        let huge = flag.intersects(Flag::R | Flag::W | Flag::X);
+        let mut page = range.start;
+        while page < range.end {
+            let current = frame + (page - range.start);
+            let mut span = 1;
+            for level in 0..(Self::conf() - 1) {
+                let size = Self::span(level);
+                if !huge || !Self::huge(level) || !page.as_usize().is_multiple_of(size) || !current.as_usize().is_multiple_of(size) || range.end - page < size {
+                    continue;
+                }
+                match Self::try_fixed_map_huge(table, page, current, flag, level) {
+                    Ok(()) => { span = size; break; },
+                    Err(Error::AlreadyMapped) => continue,
+                    Err(error) => panic!("Map page {:?} failed: {:?}", page, error),
+                }
+            }
+            if span == 1 {
+                Self::fixed_map(table, page, current, flag);
+            }
+            page += span;
+        }
+    }
+    /**
+    返回页映射的页框号、标志位与所在映射的页数，页未被映射时标志位为空
+    */
+    fn get(table: &mut Table, page_num: VirtPageNum) -> (PhysPageNum, Flag, usize) {
+        Self::try_get(table, page_num).unwrap_or((PhysPageNum::new(0), Flag::empty(), 0))
+    }
+    /**
+    同 get，页未被映射时返回 Error::NotMapped
+    */
+    fn try_get(table: &Table, page_num: VirtPageNum) -> Result<(PhysPageNum, Flag, usize), Error> {
+        let (entry, level) = Self::walk(table, page_num)?;
+        let flag = Self::flag(entry);
+        if !flag.is_valid() {
+            return Err(Error::NotMapped);
         }
 
-        current_entry = &mut current_table[index[Self::conf() - 1]];
-        (Self::frame_number(current_entry), Self::flag(current_entry))
+        let span = Self::span(level);
+        Ok((PhysPageNum::new(Self::frame_number(entry) + page_num.as_usize() % span), flag, span))
     }
     /**
     range: the page number range
     */
-    fn copy_data(table: &mut Table, range: (usize, usize), data: &[u8]) {
+    fn copy_data(table: &mut Table, range: PageRange<VirtPageNum>, data: &[u8]) {
+        Self::try_copy_data(table, range, data).expect("Page not mapped.")
+    }
+    /**
+    同 copy_data，数据所在页未被映射时返回 Error::NotMapped，此前的页已被写入
+    */
+    fn try_copy_data(table: &mut Table, range: PageRange<VirtPageNum>, data: &[u8]) -> Result<(), Error> {
         use core::slice::from_raw_parts_mut;
         use crate::memory::Address;
 
         let page_size = Address::address(1);
 
         let mut start: usize = 0;
-        let mut current = range.0;
+        let mut current = range.start;
         let len = data.len();
         loop {
             let src = &data[start..len.min(start + page_size)];
-            let (frame_number, _) = Self::get(table, current);
-            let address = Address::address(frame_number);
-            let target = unsafe{ from_raw_parts_mut(address as *mut u8, src.len()) };
+            let (frame_number, _, _) = Self::try_get(table, current)?;
+            let target = unsafe{ from_raw_parts_mut(frame_number.address().as_ptr(), src.len()) };
             target.copy_from_slice(src);
 
             start += page_size;
@@ -224,6 +372,183 @@ pub trait Lib: Hal {
             }
             current += 1;
         }
+
+        Ok(())
+    }
+    /**
+    将虚拟地址转换为物理地址，不检查标志位
+    */
+    fn translate(table: &Table, address: VirtAddr) -> Result<PhysAddr, Error> {
+        Self::translate_flag(table, address, Flag::empty())
+    }
+    /**
+    将虚拟地址转换为物理地址，页的标志位须包含 flag
+    */
+    fn translate_flag(table: &Table, address: VirtAddr, flag: Flag) -> Result<PhysAddr, Error> {
+        let (frame_number, page_flag, _) = Self::try_get(table, address.floor())?;
+        if !page_flag.contains(flag) {
+            return Err(Error::PermissionDenied);
+        }
+
+        Ok(frame_number.address() + address.offset())
+    }
+    /**
+    将用户空间的缓冲区 \[address, address + len) 转换为按页切分的内核可访问切片
+
+    flag 为所需的访问权限（Flag::R 或 Flag::W），总是要求 Flag::U；写时复制、按需分配与换出的页返回错误，需要缺页处理时使用 AddressSpace 中的同名函数
+    */
+    fn translated_byte_buffer(table: &Table, address: VirtAddr, len: usize, flag: Flag) -> Result<Vec<&'static mut [u8]>, Error> {
+        use core::slice::from_raw_parts_mut;
+
+        let end = address.as_usize().checked_add(len).and_then(VirtAddr::try_new).ok_or(Error::NotMapped)?;
+        let mut buffers = Vec::new();
+        let mut current = address;
+        while current < end {
+            let next = (current.floor() + 1).address().min(end);
+            let physical = Self::translate_flag(table, current, flag | Flag::U)?;
+            buffers.push(unsafe { from_raw_parts_mut(physical.as_ptr(), next - current) });
+            current = next;
+        }
+
+        Ok(buffers)
+    }
+    /**
+    读取用户空间中以 0 结尾的 UTF-8 字符串，不是合法的 UTF-8 时返回 Error::Encoding
+
+    不含结尾的 0 最多读取 max 字节，超出时返回 Error::TooLong
+    */
+    fn translated_str(table: &Table, address: VirtAddr, max: usize) -> Result<String, Error> {
+        let mut bytes = Vec::new();
+        let mut current = address;
+        loop {
+            let physical = Self::translate_flag(table, current, Flag::U | Flag::R)?;
+            let byte = unsafe { *physical.as_ptr::<u8>() };
+            if byte == 0 {
+                break;
+            }
+            if current - address == max {
+                return Err(Error::TooLong);
+            }
+            bytes.push(byte);
+            current += 1;
+        }
+
+        String::from_utf8(bytes).map_err(|_| Error::Encoding)
+    }
+    /**
+    返回用户空间中对象的可变引用，对象须对齐且不跨越页边界
+    */
+    fn translated_ref_mut<T>(table: &Table, address: VirtAddr) -> Result<&'static mut T, Error> {
+        use core::mem::{ align_of, size_of };
+        use crate::memory::Address;
+
+        if !address.as_usize().is_multiple_of(align_of::<T>()) || address.offset() + size_of::<T>() > Address::address(1) {
+            return Err(Error::Misaligned);
+        }
+
+        let physical = Self::translate_flag(table, address, Flag::U | Flag::R | Flag::W)?;
+        Ok(unsafe { &mut *physical.as_ptr::<T>() })
+    }
+    /**
+    写时复制地复制页表
+
+    页表持有的可写页框由两个页表共享，在两者中均被映射为只读并记录于 cow；shared 中的页与其余映射原样复制
+
+    换出的页先通过全局交换区换入，父页表的交换槽随之释放，子页表不共享交换槽
+    */
+    fn fork(table: &mut Table) -> Result<Table, Error> {
+        if table.swap.keys().any(|&page| swap::Swap::is_swapped::<Self>(table, page)) {
+            swap::access(|swap| swap.as_mut().ok_or(Error::NotMapped)?.resident::<Self>(table))?;
+        }
+
+        let root = Frame::try_new().ok_or(Error::OutOfMemory)?;
+        let mut child = Table {
+            root,
+            table: BTreeMap::new(),
+            frame: BTreeMap::new(),
+            cow: BTreeSet::new(),
+            shared: BTreeSet::new(),
+            swap: BTreeMap::new(),
+        };
+        let root = child.root.number;
+        Self::clone_table(&mut child, table.root.number, root, 0)?;
+
+        let shared: Vec<(VirtPageNum, Arc<Frame>)> = table.frame.iter()
+            .map(|(&page, frame)| (page, frame.clone()))
+            .collect();
+        for (page, frame) in shared {
+            if table.shared.contains(&page) {
+                child.shared.insert(page);
+                child.frame.insert(page, frame);
+                continue;
+            }
+
+            let (entry, _) = Self::walk(table, page)?;
+            let flag = Self::flag(entry);
+            if flag.contains(Flag::W) || table.cow.contains(&page) {
+                Self::set_flag(entry, flag - Flag::W);
+                let (entry, _) = Self::walk(&child, page)?;
+                Self::set_flag(entry, flag - Flag::W);
+
+                table.cow.insert(page);
+                child.cow.insert(page);
+            }
+            child.frame.insert(page, frame);
+        }
+
+        Ok(child)
+    }
+    /**
+    将 source 页表（第 level 级）的内容复制到 target，下一级页表被递归复制并由 child 持有
+    */
+    fn clone_table(child: &mut Table, source: usize, target: usize, level: usize) -> Result<(), Error> {
+        let source = Self::as_table(source);
+        let target = Self::as_table(target);
+
+        for (from, to) in source.iter().zip(target.iter_mut()) {
+            let flag = Self::flag(from);
+            *to = if level < Self::conf() - 1 && flag.is_valid() && !flag.is_leaf() {
+                let frame = Frame::try_new().ok_or(Error::OutOfMemory)?;
+                let frame_number = frame.number;
+                child.table.insert(frame_number, frame);
+                Self::clone_table(child, Self::frame_number(from), frame_number, level + 1)?;
+
+                Self::new_entry(frame_number, flag)
+            } else {
+                *from
+            };
+        }
+
+        Ok(())
+    }
+    /**
+    写缺页处理：复制共享的页框并恢复写权限，仅剩一个持有者时直接恢复写权限
+
+    非写时复制页返回 Error::PermissionDenied；调用者负责刷新 TLB
+    */
+    fn copy_on_write(table: &mut Table, page_num: VirtPageNum) -> Result<(), Error> {
+        if !table.cow.contains(&page_num) {
+            return Err(Error::PermissionDenied);
+        }
+
+        let (entry, _) = Self::walk(table, page_num)?;
+        let flag = Self::flag(entry);
+        if !flag.is_valid() {
+            return Err(Error::NotMapped);
+        }
+
+        if Arc::strong_count(table.frame.get(&page_num).ok_or(Error::NotMapped)?) > 1 {
+            let copy = swap::alloc::<Self>(table)?;
+            let (entry, _) = Self::walk(table, page_num)?;
+            copy.copy_from(&table.frame[&page_num]);
+            *entry = Self::new_entry(copy.number, flag | Flag::W);
+            table.frame.insert(page_num, Arc::new(copy));
+        } else {
+            Self::set_flag(entry, flag | Flag::W);
+        }
+        table.cow.remove(&page_num);
+
+        Ok(())
     }
 }
 
@@ -243,43 +568,43 @@ pub trait Hal {
     /**
     实现参考：
     ```
-    fn get_mut(table: &mut Table, page_num: usize) -> &mut Entry {
-        let index = Self::index(page_num);
-        let table = Self::as_table(table.root.number);
-
-        let mut current_entry = &mut table[index[0]];
-        let frame_number = EntryLib::frame_number(current_entry);
-        let mut current_table = Self::as_table(frame_number);
-
-        for i in 1..(Self::conf() - 1) {
-            current_entry = &mut current_table[index[i]];
-            let frame_number= EntryLib::frame_number(current_entry);
-            current_table = Self::as_table(frame_number);
-        }
-
-        current_entry = &mut current_table[index[Self::conf() - 1]];
-
-        current_entry
+    fn get_mut(table: &mut Table, page_num: VirtPageNum) -> &mut Entry {
+        Self::walk(table, page_num).expect("Page table not present.").0
     }
     ```
     */
-    fn get_mut(table: &mut Table, page_num: usize) -> &mut Entry {
-        let index = Self::index(page_num);
-        let table = Self::as_table(table.root.number);
-
-        let mut current_entry = &mut table[index[0]];
-        let frame_number = Self::frame_number(current_entry);
-        let mut current_table = Self::as_table(frame_number);
+    fn get_mut(table: &mut Table, page_num: VirtPageNum) -> &mut Entry {
+        Self::walk(table, page_num).expect("Page table not present.").0
+    }
+    /**
+    返回页号对应的叶项及其所在级数，末级页表项本身可能无效
 
-        for i in 1..(Self::conf() - 1) {
-            current_entry = &mut current_table[index[i]];
-            let frame_number= Self::frame_number(current_entry);
-            current_table = Self::as_table(frame_number);
+    逐级检查中间页表项的 V 位，中间页表不存在时返回 Error::NotMapped；遇到大页时提前返回
+    */
+    fn walk(table: &Table, page_num: VirtPageNum) -> Result<(&'static mut Entry, usize), Error> {
+        let index = Self::index(page_num.as_usize());
+
+        let mut current_table = Self::as_table(table.root.number);
+        for (level, &i) in index.iter().take(Self::conf() - 1).enumerate() {
+            let current_entry = &mut current_table[i];
+            let flag = Self::flag(current_entry);
+            if !flag.is_valid() {
+                return Err(Error::NotMapped);
+            }
+            if flag.is_leaf() {
+                return Ok((current_entry, level));
+            }
+            current_table = Self::as_table(Self::frame_number(current_entry));
         }
 
-        current_entry = &mut current_table[index[Self::conf() - 1]];
-
-        current_entry
+        Ok((&mut current_table[index[Self::conf() - 1]], Self::conf() - 1))
+    }
+    /**
+    是否允许在第 level 级建立大页映射（末级总是允许）
+    */
+    #[inline]
+    fn huge(_level: usize) -> bool {
+        true
     }
 
     fn flag(entry: &Entry) -> Flag;
@@ -287,25 +612,69 @@ pub trait Hal {
     fn set_flag(entry: &mut Entry, page_flag: Flag);
 
     fn new_entry(frame_num: usize, page_flag: Flag) -> Entry;
+    /**
+    第 level 级的叶项，末级之前为大页；大页与普通页编码不同的格式需要实现
+    */
+    #[inline]
+    fn new_leaf(frame_num: usize, page_flag: Flag, _level: usize) -> Entry {
+        Self::new_entry(frame_num, page_flag)
+    }
     
     fn frame_number(entry: &Entry) -> usize;
 }
 
 /**
-hold frame 采用树形结构设计的问题不好确定键值对
+页表持有的页框在页表销毁时释放
 */
 pub struct Table {
     pub root: Frame,
-    pub frame: Vec<Frame>,
+    /// 中间页表所在的页框，键为页框号
+    pub table: BTreeMap<usize, Frame>,
+    /// 映射到页表持有的页框，键为页号；fork 之后页框可能被多个页表共享
+    pub frame: BTreeMap<VirtPageNum, Arc<Frame>>,
+    /// 写时复制的页号
+    pub cow: BTreeSet<VirtPageNum>,
+    /// 共享映射的页号，fork 时页框可写地共享而不写时复制
+    pub shared: BTreeSet<VirtPageNum>,
+    /// 在交换区中有副本的页号与交换槽，页可能已被换出，也可能驻留且未被修改
+    pub swap: BTreeMap<VirtPageNum, usize>,
 }
 
 impl Table {
     pub fn new() -> Self {
         let root = Frame::new();
+        root.clear();
 
         Self {
             root,
-            frame: Vec::new()
+            table: BTreeMap::new(),
+            frame: BTreeMap::new(),
+            cow: BTreeSet::new(),
+            shared: BTreeSet::new(),
+            swap: BTreeMap::new(),
+        }
+    }
+    /**
+    销毁页表，释放其持有的所有页框
+
+    # 返回值
+    释放的页框数，仍被其他页表共享的页框不计入
+    */
+    pub fn destroy(self) -> usize {
+        let frame = self.frame.values().filter(|frame| Arc::strong_count(frame) == 1).count();
+
+        1 + self.table.len() + frame
+    }
+}
+
+/**
+释放页表的交换槽
+*/
+impl Drop for Table {
+    fn drop(&mut self) {
+        let page: Vec<VirtPageNum> = self.swap.keys().copied().collect();
+        for page_num in page {
+            swap::free(self, page_num);
         }
     }
 }
@@ -314,206 +683,278 @@ impl Table {
 mod test {
     use alloc::vec;
     use alloc::vec::Vec;
-    use crate::memory::{ Flag, Address };
-
-    use super::{
-        Hal, Table, Lib as T, 
-        entry::{ Entry, Lib as E },
-        frame::Frame
-    };
-
-    struct TableLib;
+    use crate::memory::{ Flag, VirtPageNum, PhysPageNum, PageRange };
 
-    struct EntryLib;
+    use super::{ Hal, Table, Error, Lib as _ };
 
-    const FRAME_NUMBER_MASK: usize = 0b111_111_111_111_111_111_111_111_111_111_111_111_111_111_110_000_000_000;
-    const FLAG_MASK: usize = 0b11_111_111_111;
+    type TableLib = super::format::Sv39;
 
-    impl E for EntryLib {
-        fn new(frame_num: usize, page_flag: Flag) -> Entry {
-            let frame_number_bits = frame_num << FRAME_NUMBER_MASK.trailing_zeros();
-            let flag_bits = (page_flag.bits as usize) << FLAG_MASK.trailing_zeros();
+    /**
+    所有测试共享同一个页框分配器，页框来自模拟的物理内存
+    */
+    fn init() {
+        use lazy_static::lazy_static;
 
-            Entry::from_bits(frame_number_bits | flag_bits)
+        lazy_static! {
+            static ref POOL: PageRange<PhysPageNum> = super::sim::init(config::FRAME);
         }
 
-        fn frame_number(entry: &Entry) -> usize {
-            (entry.bits() & FRAME_NUMBER_MASK) >> FRAME_NUMBER_MASK.trailing_zeros()
-        }
+        lazy_static::initialize(&POOL);
+    }
 
-        fn flag(entry: &Entry) -> Flag {
-            let flag = (entry.bits() & FLAG_MASK) >> FLAG_MASK.trailing_zeros();
+    #[test]
+    fn map() {
+        init();
 
-            Flag::from_bits(flag as u8).unwrap()
-        }
+        let mut table = Table::new();
+        TableLib::fixed_map(&mut table, VirtPageNum::new(0), PhysPageNum::new(0), Flag::V);
 
-        fn flag_set(entry: &mut Entry, page_flag: Flag) {
-            let frame_number_bits = entry.bits() & FRAME_NUMBER_MASK;
-            let flag_bits = (page_flag.bits as usize) << FLAG_MASK.trailing_zeros();
+        let (number, flag, size) = TableLib::get(&mut table, VirtPageNum::new(0));
 
-            entry.bits_set(frame_number_bits | flag_bits); 
-        }
+        assert_eq!(number, PhysPageNum::new(0));
+        assert_eq!(flag, Flag::V);
+        assert_eq!(size, 1);
     }
 
-    impl Hal for TableLib {
-        fn index(page_num: usize) -> Vec<usize> {
-            let mut page_num = page_num;
-            let mut index = [0usize; 3];
+    #[test]
+    fn unmap() {
+        init();
 
-            index[2] = page_num & 0b111_111_111;
-            for i in (0..2).rev() {        
-                page_num >>= 9;
-                index[i] = page_num & 0b111_111_111;
-            }
+        let mut table = Table::new();
+        TableLib::map_area(&mut table, PageRange::inclusive(VirtPageNum::new(0x1ff), VirtPageNum::new(0x200)), Flag::R | Flag::W);
+        TableLib::fixed_map(&mut table, VirtPageNum::new(0x40000), PhysPageNum::new(7), Flag::R);
+        assert_eq!(table.frame.len(), 2);
+        // two second-level tables, three third-level tables
+        assert_eq!(table.table.len(), 5);
+
+        TableLib::unmap(&mut table, VirtPageNum::new(0x200));
+        assert_eq!(table.frame.len(), 1);
+        assert_eq!(table.table.len(), 4);
+        assert!(!TableLib::get(&mut table, VirtPageNum::new(0x1ff)).1.is_empty());
+
+        TableLib::unmap_area(&mut table, PageRange::inclusive(VirtPageNum::new(0x1ff), VirtPageNum::new(0x1ff)));
+        TableLib::unmap(&mut table, VirtPageNum::new(0x40000));
+        assert!(table.frame.is_empty());
+        assert!(table.table.is_empty());
+
+        TableLib::map(&mut table, VirtPageNum::new(0), Flag::R);
+        assert_eq!(table.destroy(), 4);
+    }
 
-            index.to_vec()
-        }
+    #[test]
+    fn error() {
+        init();
 
-        #[inline]
-        fn conf() -> usize {
-            3
-        }
+        let mut table = Table::new();
+        let page = VirtPageNum::new(0x12345);
+        assert_eq!(TableLib::try_get(&table, page), Err(Error::NotMapped));
+        assert!(TableLib::walk(&table, page).is_err());
+        assert_eq!(TableLib::get(&mut table, page), (PhysPageNum::new(0), Flag::empty(), 0));
+        assert_eq!(TableLib::try_unmap(&mut table, page), Err(Error::NotMapped));
+
+        TableLib::try_map(&mut table, page, Flag::R).unwrap();
+        assert_eq!(TableLib::try_map(&mut table, page, Flag::W), Err(Error::AlreadyMapped));
+        assert_eq!(TableLib::try_fixed_map(&mut table, page, PhysPageNum::new(7), Flag::R), Err(Error::AlreadyMapped));
+        assert_eq!(TableLib::try_get(&table, page).unwrap().1, Flag::V | Flag::R);
+        // the leaf table exists, its neighbour is still unmapped
+        assert_eq!(TableLib::try_get(&table, page + 1), Err(Error::NotMapped));
+        assert_eq!(TableLib::try_copy_data(&mut table, PageRange::inclusive(page, page + 1), &[1u8; 4097]), Err(Error::NotMapped));
+
+        TableLib::try_unmap(&mut table, page).unwrap();
+        assert_eq!(table.destroy(), 1);
+    }
 
-        #[inline]
-        fn flag(entry: &Entry) -> Flag {
-            EntryLib::flag(entry)
-        }
+    #[test]
+    fn huge() {
+        init();
 
-        #[inline]
-        fn new_entry(frame_num: usize, page_flag: Flag) -> Entry {
-            EntryLib::new(frame_num, page_flag)
-        }
+        let mut table = Table::new();
+        // 1 GiB + 2 MiB + 1 page, starting at a 1 GiB boundary
+        let (start, end) = (VirtPageNum::new(0x40000), VirtPageNum::new(0x40000 + 0x40000 + 0x200));
+        TableLib::fixed_map_area(&mut table, PageRange::inclusive(start, end), PhysPageNum::new(0x80000), Flag::R | Flag::W);
+        // one second-level table for the 2 MiB page, one third-level table for the last page
+        assert_eq!(table.table.len(), 2);
+
+        assert_eq!(TableLib::get(&mut table, start + 5), (PhysPageNum::new(0x80005), Flag::V | Flag::R | Flag::W, 0x40000));
+        assert_eq!(TableLib::get(&mut table, VirtPageNum::new(0x80000 + 7)).2, 0x200);
+        assert_eq!(TableLib::get(&mut table, end), (PhysPageNum::new(0x80000 + 0x40200), Flag::V | Flag::R | Flag::W, 1));
+
+        // unmapping one page splits the gigapage down to 4 KiB pages
+        TableLib::unmap(&mut table, start + 0x201);
+        assert_eq!(TableLib::try_get(&table, start + 0x201), Err(Error::NotMapped));
+        assert_eq!(TableLib::get(&mut table, start + 0x200).2, 1);
+        assert_eq!(TableLib::get(&mut table, start + 0x400), (PhysPageNum::new(0x80400), Flag::V | Flag::R | Flag::W, 0x200));
+        assert_eq!(table.table.len(), 4);
+        assert_eq!(table.destroy(), 5);
+    }
 
-        #[inline]
-        fn frame_number(entry: &Entry) -> usize {
-            EntryLib::frame_number(entry)
-        }
+    #[test]
+    fn translate() {
+        init();
 
-        #[inline]
-        fn set_flag(entry: &mut Entry, page_flag: Flag) {
-            EntryLib::flag_set(entry, page_flag);
+        let mut table = Table::new();
+        TableLib::map_area(&mut table, PageRange::inclusive(VirtPageNum::new(0x10), VirtPageNum::new(0x11)), Flag::U | Flag::R | Flag::W);
+        TableLib::map(&mut table, VirtPageNum::new(0x12), Flag::U | Flag::R);
+        TableLib::map(&mut table, VirtPageNum::new(0x13), Flag::R | Flag::W);
+
+        let address = VirtPageNum::new(0x10).address() + 0xffe;
+        let (frame, _, _) = TableLib::get(&mut table, VirtPageNum::new(0x10));
+        assert_eq!(TableLib::translate(&table, address), Ok(frame.address() + 0xffe));
+
+        // a buffer crossing the page boundary is split into two slices
+        let buffer = TableLib::translated_byte_buffer(&table, address, 6, Flag::W).unwrap();
+        assert_eq!(buffer.iter().map(|slice| slice.len()).collect::<Vec<_>>(), vec![2, 4]);
+        for (slice, data) in buffer.into_iter().zip([&b"he"[..], &b"llo\0"[..]]) {
+            slice.copy_from_slice(data);
         }
+        assert_eq!(TableLib::translated_str(&table, address, 5).unwrap(), "hello");
+        assert_eq!(TableLib::translated_str(&table, address, 4), Err(Error::TooLong));
+
+        // multi-byte characters are decoded, invalid UTF-8 is rejected
+        let path = VirtPageNum::new(0x11).address() + 0x100;
+        TableLib::translated_byte_buffer(&table, path, 8, Flag::W).unwrap()[0].copy_from_slice("/tmp/é\0".as_bytes());
+        assert_eq!(TableLib::translated_str(&table, path, 16).unwrap(), "/tmp/é");
+        TableLib::translated_byte_buffer(&table, path, 2, Flag::W).unwrap()[0].copy_from_slice(&[0xc3, 0]);
+        assert_eq!(TableLib::translated_str(&table, path, 16), Err(Error::Encoding));
+
+        let value = TableLib::translated_ref_mut::<u64>(&table, VirtPageNum::new(0x11).address() + 8).unwrap();
+        *value = 7;
+        assert_eq!(*TableLib::translated_ref_mut::<u64>(&table, VirtPageNum::new(0x11).address() + 8).unwrap(), 7);
+        assert_eq!(TableLib::translated_ref_mut::<u64>(&table, VirtPageNum::new(0x11).address() + 4).err(), Some(Error::Misaligned));
+        assert_eq!(TableLib::translated_ref_mut::<u64>(&table, address).err(), Some(Error::Misaligned));
+
+        // read-only, kernel-only and unmapped pages
+        assert!(TableLib::translated_byte_buffer(&table, VirtPageNum::new(0x12).address(), 1, Flag::R).is_ok());
+        assert_eq!(TableLib::translated_byte_buffer(&table, VirtPageNum::new(0x12).address(), 1, Flag::W).err(), Some(Error::PermissionDenied));
+        assert_eq!(TableLib::translated_str(&table, VirtPageNum::new(0x13).address(), 16).err(), Some(Error::PermissionDenied));
+        assert_eq!(TableLib::translated_byte_buffer(&table, VirtPageNum::new(0x14).address(), 1, Flag::R).err(), Some(Error::NotMapped));
     }
 
-    impl T for TableLib { 
-        fn map(table: &mut Table, page_num: usize, page_flag: Flag) {
-            let index = Self::index(page_num);
-            let root = Self::as_table(table.root.number);
-
-            let mut current_entry = &mut root[index[0]];
-            let mut current_table = if !EntryLib::flag(current_entry).is_valid() {
-                let frame = Frame::new();
-                let frame_number = frame.number;
-                *current_entry = EntryLib::new(frame_number, Flag::V);
-                table.frame.push(frame);
-
-                Self::as_table(frame_number)
-            } else {
-                let frame_number = EntryLib::frame_number(current_entry);
-                Self::as_table(frame_number)
-            };
+    #[test]
+    fn fork() {
+        init();
+
+        let mut parent = Table::new();
+        let page = VirtPageNum::new(0x20);
+        TableLib::map_area(&mut parent, PageRange::inclusive(page, page + 1), Flag::U | Flag::R | Flag::W);
+        TableLib::map(&mut parent, page + 2, Flag::U | Flag::R | Flag::X);
+        TableLib::fixed_map(&mut parent, page + 3, PhysPageNum::new(7), Flag::R);
+        TableLib::translated_byte_buffer(&parent, page.address(), 1, Flag::W).unwrap()[0][0] = 1;
+
+        let mut child = TableLib::fork(&mut parent).unwrap();
+        assert_eq!(child.table.len(), parent.table.len());
+        assert_eq!(child.cow.iter().copied().collect::<Vec<_>>(), vec![page, page + 1]);
+        assert_eq!(TableLib::get(&mut child, page), TableLib::get(&mut parent, page));
+        assert_eq!(TableLib::get(&mut child, page + 3).0, PhysPageNum::new(7));
+        assert_eq!(TableLib::get(&mut parent, page).1, Flag::V | Flag::U | Flag::R);
+        assert_eq!(TableLib::translated_byte_buffer(&child, page.address(), 1, Flag::W).err(), Some(Error::PermissionDenied));
+
+        // the child copies on first write, the parent becomes the only holder
+        assert_eq!(TableLib::copy_on_write(&mut child, page + 2), Err(Error::PermissionDenied));
+        TableLib::copy_on_write(&mut child, page).unwrap();
+        assert_ne!(TableLib::get(&mut child, page).0, TableLib::get(&mut parent, page).0);
+        TableLib::translated_byte_buffer(&child, page.address(), 1, Flag::W).unwrap()[0][0] = 2;
+        assert_eq!(TableLib::translated_byte_buffer(&parent, page.address(), 1, Flag::R).unwrap()[0][0], 1);
+
+        let frame = TableLib::get(&mut parent, page).0;
+        TableLib::copy_on_write(&mut parent, page).unwrap();
+        assert_eq!(TableLib::get(&mut parent, page), (frame, Flag::V | Flag::U | Flag::R | Flag::W, 1));
+
+        // 0x21 and 0x22 are still shared
+        let tables = 1 + child.table.len();
+        assert_eq!(child.destroy(), tables + 1);
+        assert_eq!(parent.destroy(), 1 + 2 + 3);
+    }
 
-            for i in 1..(Self::conf() - 1) {
-                current_entry = &mut current_table[index[i]];
-                current_table = if !EntryLib::flag(current_entry).is_valid() {
-                    let frame = Frame::new();
-                    let frame_number = frame.number;
-                    *current_entry = EntryLib::new(frame_number, Flag::V);
-                    table.frame.push(frame);
-
-                    Self::as_table(frame_number)
-                } else {
-                    let frame_number = EntryLib::frame_number(current_entry);
-                    Self::as_table(frame_number)
-                }
+    #[test]
+    fn swap() {
+        use alloc::boxed::Box;
+        use alloc::sync::Arc;
+        use core::sync::atomic::{ AtomicUsize, Ordering };
+        use crate::peripheral::Block;
+        use super::swap::{ self, Swap };
+
+        struct Disk(Vec<u8>, Arc<AtomicUsize>);
+
+        impl Block for Disk {
+            fn read(&mut self, address: usize, cache: &mut [u8]) {
+                cache.copy_from_slice(&self.0[address * 512..(address + 1) * 512]);
             }
 
-            current_entry = &mut current_table[index[Self::conf() - 1]];
-            if !EntryLib::flag(current_entry).is_valid() {
-                let frame = Frame::new();
-                *current_entry = EntryLib::new(frame.number, Flag::V | page_flag);
-                table.frame.push(frame);
+            fn write(&mut self, address: usize, cache: &[u8]) {
+                self.1.fetch_add(1, Ordering::Relaxed);
+                self.0[address * 512..(address + 1) * 512].copy_from_slice(cache);
             }
         }
 
-        fn unmap(table: &mut Table, page_num: usize) {
-            let entry = Self::get_mut(table, page_num);
+        init();
 
-            EntryLib::flag_set(entry, Flag::empty());
+        fn evict(table: &mut Table) -> Result<VirtPageNum, Error> {
+            swap::access(|swap| swap.as_mut().unwrap().evict::<TableLib>(table))
         }
-
-        fn fixed_map(table: &mut Table, page_num: usize, frame_num: usize, page_flag: Flag) {
-            let index = Self::index(page_num); let root = Self::as_table(table.root.number);
-
-            let mut current_entry = &mut root[index[0]];
-            let mut current_table = if !EntryLib::flag(current_entry).is_valid() {
-                let frame = Frame::new();
-                let frame_number = frame.number;
-                *current_entry = EntryLib::new(frame_number, Flag::V);
-                table.frame.push(frame);
-                Self::as_table(frame_number)
-            } else { 
-                let frame_number = EntryLib::frame_number(current_entry);
-                Self::as_table(frame_number)
-            };
-
-            for i in 1..(Self::conf() - 1) {
-                current_entry = &mut current_table[index[i]]; 
-                current_table = if !EntryLib::flag(current_entry).is_valid() {
-                    let frame = Frame::new();
-                    let frame_number = frame.number;
-                    *current_entry = EntryLib::new(frame_number, Flag::V);
-                    table.frame.push(frame);
-
-                    Self::as_table(frame_number)
-                } else {
-                    let frame_number = EntryLib::frame_number(current_entry);
-
-                    Self::as_table(frame_number)
-                }
-            }
-
-            current_entry = &mut current_table[index[Self::conf() - 1]];
-            *current_entry = EntryLib::new(frame_num, page_flag | Flag::V);
+        fn used() -> usize {
+            swap::access(|swap| swap.as_ref().unwrap().used())
         }
 
-        fn get(table: &mut Table, page_num: usize) -> (usize, Flag) {
-            let index = Self::index(page_num); let root = Self::as_table(table.root.number);
-
-            let mut current_entry = &mut root[index[0]];
-            let frame_number = EntryLib::frame_number(current_entry);
-            let mut current_table = Self::as_table(frame_number);
-
-            for i in 1..(Self::conf() - 1) {
-                current_entry = &mut current_table[index[i]];
-                let frame_number = EntryLib::frame_number(current_entry);
-                current_table = Self::as_table(frame_number);
-            }
-
-            current_entry = &mut current_table[index[Self::conf() - 1]];
-            (EntryLib::frame_number(current_entry), EntryLib::flag(current_entry))
-        }
-
-        fn copy_data(_table: &mut Table, _range: (usize, usize), _data: &[u8]) {
-            todo!()
+        let write = Arc::new(AtomicUsize::new(0));
+        swap::init(Swap::new(Box::new(Disk(vec![0; 16 * 4096], write.clone())), 8, 4));
+        let mut table = Table::new();
+        let range = PageRange::inclusive(VirtPageNum::new(0x30), VirtPageNum::new(0x33));
+        TableLib::map_area(&mut table, range, Flag::U | Flag::R | Flag::W);
+        for page in range {
+            TableLib::translated_byte_buffer(&table, page.address(), 1, Flag::W).unwrap()[0][0] = page.as_usize() as u8;
+            let entry = TableLib::get_mut(&mut table, page);
+            let flag = TableLib::flag(entry);
+            TableLib::set_flag(entry, flag | Flag::A | Flag::D);
         }
+        // only 0x31 has not been accessed recently
+        let entry = TableLib::get_mut(&mut table, VirtPageNum::new(0x31));
+        let flag = TableLib::flag(entry);
+        TableLib::set_flag(entry, flag - Flag::A);
+
+        assert_eq!(evict(&mut table), Ok(VirtPageNum::new(0x31)));
+        assert!(Swap::is_swapped::<TableLib>(&table, VirtPageNum::new(0x31)));
+        assert_eq!(TableLib::try_get(&table, VirtPageNum::new(0x31)), Err(Error::NotMapped));
+        assert_eq!(table.frame.len(), 3);
+        assert_eq!(write.load(Ordering::Relaxed), 8);
+        // mapping a mapped or swapped page fails before a frame is allocated
+        assert_eq!(TableLib::try_map(&mut table, VirtPageNum::new(0x31), Flag::U | Flag::R), Err(Error::AlreadyMapped));
+        assert_eq!(table.frame.len(), 3);
+
+        // 0x30 lost its A bit when the hand passed it
+        assert_eq!(evict(&mut table), Ok(VirtPageNum::new(0x30)));
+        assert_eq!(write.load(Ordering::Relaxed), 16);
+
+        swap::access(|swap| swap.as_mut().unwrap().swap_in::<TableLib>(&mut table, VirtPageNum::new(0x31))).unwrap();
+        assert_eq!(TableLib::translated_byte_buffer(&table, VirtPageNum::new(0x31).address(), 1, Flag::R).unwrap()[0][0], 0x31);
+        assert_eq!(TableLib::get(&mut table, VirtPageNum::new(0x31)).1, Flag::V | Flag::U | Flag::R | Flag::W);
+
+        // a clean page with a copy in the swap area is evicted first, without writing
+        assert_eq!(evict(&mut table), Ok(VirtPageNum::new(0x31)));
+        assert_eq!(write.load(Ordering::Relaxed), 16);
+        assert_eq!(used(), 2);
+
+        // unmapping frees the slot of a swapped page
+        TableLib::unmap(&mut table, VirtPageNum::new(0x30));
+        assert_eq!(used(), 1);
+
+        // fork swaps the page in first, the child does not share slots
+        let child = TableLib::fork(&mut table).unwrap();
+        assert!(table.swap.is_empty() && child.swap.is_empty());
+        assert_eq!(used(), 0);
+        assert_eq!(TableLib::translated_byte_buffer(&child, VirtPageNum::new(0x31).address(), 1, Flag::R).unwrap()[0][0], 0x31);
+        child.destroy();
+
+        // destroying a table frees its slots
+        TableLib::copy_on_write(&mut table, VirtPageNum::new(0x32)).unwrap();
+        assert_eq!(evict(&mut table), Ok(VirtPageNum::new(0x32)));
+        assert_eq!(used(), 1);
+        table.destroy();
+        assert_eq!(used(), 0);
     }
 
-    #[test]
-    fn map() {
-        let mut frame = vec![0usize; 5 * 512];
-
-        let head = (&mut frame[511]) as *mut _ as usize;
-        let head = Address::number(head);
-        let tail = head + 2;
-        Frame::init(head, tail);
-
-        let mut table = Table::new();
-        TableLib::fixed_map(&mut table, 0, 0, Flag::V);
-
-        let (number, flag) = TableLib::get(&mut table, 0);
-
-        assert_eq!(number, 0);
-        assert_eq!(flag, Flag::V);
+    mod config {
+        /// 测试使用的页框数
+        pub const FRAME: usize = 64;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,99 @@
+/*!
+共享内存对象
+
+对象持有一组页框，可由 AddressSpace::shm_map 以各自的权限映射到多个进程的地址空间
+
+名字表、打开的句柄（Arc<Object>）与映射了对象的页表各自持有引用：对象在名字被 unlink 且最后一个句柄释放后销毁，页框在最后一个映射解除后回收
+
+# 结构体
+Object
+*/
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{ Error, frame::Frame };
+
+pub struct Object {
+    /// 对象的页框，创建时清零
+    pub frame: Vec<Arc<Frame>>,
+}
+
+impl Object {
+    /**
+    创建 len 页的匿名对象，不进入名字表
+    */
+    pub fn new(len: usize) -> Result<Arc<Self>, Error> {
+        if len == 0 {
+            return Err(Error::Invalid);
+        }
+
+        let mut frame = Vec::with_capacity(len);
+        for _ in 0..len {
+            let new = Frame::try_new().ok_or(Error::OutOfMemory)?;
+            new.clear();
+            frame.push(Arc::new(new));
+        }
+
+        Ok(Arc::new(Self { frame }))
+    }
+    /**
+    页数
+    */
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.frame.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.frame.is_empty()
+    }
+}
+
+/**
+打开名为 name 的对象，不存在时创建 len 页的对象；已存在时忽略 len
+*/
+pub fn create(name: &str, len: usize) -> Result<Arc<Object>, Error> {
+    access(|names| {
+        if let Some(object) = names.get(name) {
+            return Ok(object.clone());
+        }
+
+        let object = Object::new(len)?;
+        names.insert(String::from(name), object.clone());
+
+        Ok(object)
+    })
+}
+/**
+打开名为 name 的对象
+*/
+pub fn open(name: &str) -> Option<Arc<Object>> {
+    access(|names| names.get(name).cloned())
+}
+/**
+从名字表中移除 name，已打开的句柄与已建立的映射不受影响；name 不存在时返回 false
+*/
+pub fn unlink(name: &str) -> bool {
+    access(|names| names.remove(name).is_some())
+}
+
+use spin::Mutex;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref NAMES: Mutex<BTreeMap<String, Arc<Object>>> = Mutex::new(BTreeMap::new());
+}
+/**
+Access the name table of shared memory objects.
+*/
+#[inline]
+pub fn access<F, V>(f: F) -> V
+where
+    F: FnOnce(&mut BTreeMap<String, Arc<Object>>) -> V,
+{
+    let mut mutex = NAMES.lock();
+    f(&mut mutex)
+}
@@ -0,0 +1,352 @@
+/*!
+在主机上模拟的物理内存与 MMU，用于测试页表
+
+- 物理内存：泄漏的堆内存，由页框分配器管理，页框号即主机地址的页号
+- MMU：按硬件的方式遍历页表，检查权限并更新 A、D 位，失败时报告异常
+
+仅在测试或启用 feature sim 时编译
+
+```
+use ones::memory::{ Flag, VirtPageNum, page::{ Lib, Table, format::Sv39, sim::{ self, Mmu } } };
+
+sim::init(64);
+let mut table = Table::new();
+let page = VirtPageNum::new(0x10);
+Sv39::map(&mut table, page, Flag::R | Flag::W);
+Mmu::<Sv39>::new(false).store(&table, page.address(), b"hello").unwrap();
+```
+
+# 函数
+init()
+
+contains()
+
+# 结构体
+Mmu
+*/
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::{
+    intervene::Cause,
+    memory::{ Flag, Address, VirtAddr, PhysAddr, PhysPageNum, PageRange },
+};
+use super::{ Lib, Table, frame::Frame };
+
+lazy_static! {
+    static ref MEMORY: Mutex<Vec<PageRange<PhysPageNum>>> = Mutex::new(Vec::new());
+}
+
+/**
+分配 frames 个页框作为模拟的物理内存，并作为新的区域加入页框分配器
+
+可多次调用，返回本次加入的页框号区间
+*/
+pub fn init(frames: usize) -> PageRange<PhysPageNum> {
+    let memory: &'static mut [u8] = vec![0u8; (frames + 1) * Address::address(1)].leak();
+    let start = PhysPageNum::new(Address::ceil(memory.as_ptr() as usize));
+    let range = PageRange::new(start, start + frames);
+
+    // register the range before any frame of it can be allocated
+    MEMORY.lock().push(range);
+    Frame::zone_add(start.as_usize(), start.as_usize() + frames - 1);
+
+    range
+}
+/**
+页框是否属于模拟的物理内存
+*/
+pub fn contains(number: PhysPageNum) -> bool {
+    MEMORY.lock().iter().any(|range| range.contains(number))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// 缺页异常：Cause::PageLoadFault、Cause::PageStoreFault 或 Cause::PageInstructionFault
+    Page(Cause),
+    /// 物理地址不在模拟的物理内存中
+    Access(PhysAddr),
+}
+
+/**
+模拟 RISC-V 的 MMU，A、D 位由硬件更新（Svadu），不模拟 TLB
+*/
+pub struct Mmu<L: Lib> {
+    /// 是否以用户态访问
+    pub user: bool,
+    /// 内核态能否访问用户页，同 sstatus.SUM
+    pub sum: bool,
+    _marker: PhantomData<L>,
+}
+
+impl<L: Lib> Mmu<L> {
+    pub fn new(user: bool) -> Self {
+        Self { user, sum: false, _marker: PhantomData }
+    }
+    /**
+    按硬件的方式翻译地址，access 为 Flag::R、Flag::W 或 Flag::X
+
+    以下情况报告缺页异常：
+    - 地址不是有效位数的符号扩展形式
+    - 页表项无效，或可写而不可读
+    - 末级页表项不是叶项
+    - 权限不足，或用户态访问内核页、内核态访问用户页
+    - 大页的页框号未按大页大小对齐
+
+    成功时叶项置 A 位，写访问同时置 D 位
+    */
+    pub fn translate(&self, table: &Table, address: VirtAddr, access: Flag) -> Result<PhysAddr, Fault> {
+        let fault = Fault::Page(if access.contains(Flag::W) {
+            Cause::PageStoreFault
+        } else if access.contains(Flag::X) {
+            Cause::PageInstructionFault
+        } else {
+            Cause::PageLoadFault
+        });
+
+        let shift = usize::BITS as usize - (12 + 9 * L::conf());
+        if (((address.as_usize() << shift) as isize) >> shift) as usize != address.as_usize() {
+            return Err(fault);
+        }
+
+        let page = address.floor().as_usize();
+        let index = L::index(page);
+        let mut current = table.root.number;
+        for (level, &i) in index.iter().enumerate() {
+            let entry = &mut L::as_table(current)[i];
+            let flag = L::flag(entry);
+            if !flag.is_valid() || flag.contains(Flag::W) && !flag.contains(Flag::R) {
+                return Err(fault);
+            }
+            if !flag.is_leaf() {
+                current = L::frame_number(entry);
+                continue;
+            }
+
+            if !flag.contains(access) || self.user && !flag.contains(Flag::U) {
+                return Err(fault);
+            }
+            if !self.user && flag.contains(Flag::U) && (!self.sum || access.contains(Flag::X)) {
+                return Err(fault);
+            }
+
+            let span = 1 << (9 * (L::conf() - 1 - level));
+            let base = L::frame_number(entry);
+            if !base.is_multiple_of(span) {
+                return Err(fault);
+            }
+
+            let mut update = flag | Flag::A;
+            if access.contains(Flag::W) {
+                update |= Flag::D;
+            }
+            if update != flag {
+                L::set_flag(entry, update);
+            }
+
+            return Ok(PhysPageNum::new(base + page % span).address() + address.offset());
+        }
+
+        // the last level is not a leaf
+        Err(fault)
+    }
+
+    pub fn load(&self, table: &Table, address: VirtAddr, buffer: &mut [u8]) -> Result<(), Fault> {
+        self.access(table, address, buffer.len(), Flag::R, |physical, start, size| unsafe {
+            core::ptr::copy_nonoverlapping(physical.as_ptr::<u8>(), buffer[start..].as_mut_ptr(), size);
+        })
+    }
+
+    pub fn store(&self, table: &Table, address: VirtAddr, data: &[u8]) -> Result<(), Fault> {
+        self.access(table, address, data.len(), Flag::W, |physical, start, size| unsafe {
+            core::ptr::copy_nonoverlapping(data[start..].as_ptr(), physical.as_ptr::<u8>(), size);
+        })
+    }
+    /**
+    逐页翻译并访问 \[address, address + len)，某一页失败时之前的页已被访问
+    */
+    fn access<F>(&self, table: &Table, address: VirtAddr, len: usize, flag: Flag, mut f: F) -> Result<(), Fault>
+    where
+        F: FnMut(PhysAddr, usize, usize),
+    {
+        let mut done = 0;
+        while done < len {
+            let current = address + done;
+            let size = (Address::address(1) - current.offset()).min(len - done);
+
+            let physical = self.translate(table, current, flag)?;
+            if !contains(physical.floor()) {
+                return Err(Fault::Access(physical));
+            }
+            f(physical, done, size);
+            done += size;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::collections::BTreeMap;
+    use alloc::vec::Vec;
+    use rand::{ Rng, SeedableRng, rngs::StdRng };
+    use crate::{
+        intervene::Cause,
+        memory::{ Flag, VirtAddr, VirtPageNum, PhysPageNum, PageRange },
+    };
+    use super::{ Mmu, Fault, contains, super::{ Table, Lib, Hal, format::{ Sv39, Sv48, X86_64, AArch64_4K } } };
+
+    fn init() {
+        use lazy_static::lazy_static;
+
+        lazy_static! {
+            static ref MEMORY: PageRange<PhysPageNum> = super::init(config::FRAME);
+        }
+
+        lazy_static::initialize(&MEMORY);
+    }
+
+    #[test]
+    fn mmu() {
+        init();
+
+        let mut table = Table::new();
+        let page = VirtPageNum::new(0x40);
+        Sv39::map(&mut table, page, Flag::U | Flag::R | Flag::W);
+        Sv39::map(&mut table, page + 1, Flag::R | Flag::X);
+        Sv39::fixed_map(&mut table, page + 2, PhysPageNum::new(7), Flag::R);
+
+        let user = Mmu::<Sv39>::new(true);
+        let mut kernel = Mmu::<Sv39>::new(false);
+        let mut buffer = [0u8; 5];
+
+        // the store crosses into a kernel page
+        assert_eq!(user.store(&table, page.address() + 0xffe, b"hello"), Err(Fault::Page(Cause::PageStoreFault)));
+        assert_eq!(Sv39::get(&mut table, page).1, Flag::V | Flag::U | Flag::R | Flag::W | Flag::A | Flag::D);
+        assert_eq!(Sv39::get(&mut table, page + 1).1, Flag::V | Flag::R | Flag::X);
+
+        user.store(&table, page.address() + 8, b"hello").unwrap();
+        assert_eq!(kernel.load(&table, page.address() + 8, &mut buffer), Err(Fault::Page(Cause::PageLoadFault)));
+        kernel.sum = true;
+        kernel.load(&table, page.address() + 8, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"hello");
+        assert_eq!(kernel.translate(&table, page.address(), Flag::X), Err(Fault::Page(Cause::PageInstructionFault)));
+
+        assert!(kernel.translate(&table, (page + 1).address(), Flag::X).is_ok());
+        assert_eq!(Sv39::get(&mut table, page + 1).1, Flag::V | Flag::R | Flag::X | Flag::A);
+        assert_eq!(kernel.store(&table, (page + 1).address(), b"x"), Err(Fault::Page(Cause::PageStoreFault)));
+
+        // frame 7 is outside the simulated memory
+        assert_eq!(kernel.load(&table, (page + 2).address(), &mut buffer), Err(Fault::Access(PhysPageNum::new(7).address())));
+        assert_eq!(kernel.translate(&table, VirtAddr::new(1 << 37), Flag::R), Err(Fault::Page(Cause::PageLoadFault)));
+
+        // write-only and misaligned huge leaves are reserved
+        Sv39::fixed_map(&mut table, page + 3, PhysPageNum::new(8), Flag::W);
+        assert_eq!(kernel.store(&table, (page + 3).address(), b"x"), Err(Fault::Page(Cause::PageStoreFault)));
+        let entry = Sv39::try_entry(&mut table, VirtPageNum::new(0x200), 1).unwrap();
+        *entry = Sv39::new_entry(0x201, Flag::V | Flag::R);
+        assert_eq!(kernel.translate(&table, VirtPageNum::new(0x200).address(), Flag::R), Err(Fault::Page(Cause::PageLoadFault)));
+        *entry = Sv39::new_entry(0, Flag::empty());
+
+        table.destroy();
+    }
+
+    /**
+    随机地映射、解除映射与查询，与 BTreeMap 的结果比较，并经 MMU 读写每个页
+    */
+    fn property<L: Lib>(seed: u64) {
+        init();
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        // pages spread over several tables of each level
+        let page: Vec<VirtPageNum> = [0, 0x1ff, 0x200, 0x3_fe00, 0x4_0000, 0x3ff_ffff].iter()
+            .flat_map(|&base: &usize| (0..3).map(move |i| VirtPageNum::new(base.saturating_sub(i))))
+            .collect();
+        let flags = [Flag::R, Flag::R | Flag::W, Flag::R | Flag::X, Flag::U | Flag::R | Flag::W];
+
+        let mut table = Table::new();
+        let mut reference: BTreeMap<VirtPageNum, (Flag, u8)> = BTreeMap::new();
+        let mmu = Mmu::<L> { user: false, sum: true, _marker: core::marker::PhantomData };
+
+        for round in 0..400 {
+            let current = page[rng.gen_range(0..page.len())];
+            match rng.gen_range(0..3) {
+                0 => {
+                    let flag = flags[rng.gen_range(0..flags.len())];
+                    let result = L::try_map(&mut table, current, flag);
+                    if reference.contains_key(&current) {
+                        assert_eq!(result, Err(super::super::Error::AlreadyMapped));
+                    } else {
+                        result.unwrap();
+                        // tag the frame directly, the page may be read-only
+                        let tag = round as u8;
+                        let (frame, _, _) = L::get(&mut table, current);
+                        unsafe { *frame.address().as_ptr::<u8>() = tag; }
+                        reference.insert(current, (flag, tag));
+                    }
+                },
+                1 => {
+                    let result = L::try_unmap(&mut table, current);
+                    assert_eq!(result.is_ok(), reference.remove(&current).is_some());
+                },
+                _ => {
+                    let mut byte = [0u8];
+                    match reference.get(&current) {
+                        Some(&(flag, tag)) => {
+                            mmu.load(&table, current.address(), &mut byte).unwrap();
+                            assert_eq!(byte[0], tag);
+                            assert!(L::get(&mut table, current).1.contains(flag | Flag::V | Flag::A));
+                            assert_eq!(mmu.store(&table, current.address(), &byte).is_ok(), flag.contains(Flag::W));
+                        },
+                        None => {
+                            assert_eq!(L::try_get(&table, current), Err(super::super::Error::NotMapped));
+                            assert_eq!(mmu.load(&table, current.address(), &mut byte), Err(Fault::Page(Cause::PageLoadFault)));
+                        },
+                    }
+                },
+            }
+
+            assert_eq!(table.frame.keys().copied().collect::<Vec<_>>(), reference.keys().copied().collect::<Vec<_>>());
+            for (&number, frame) in table.frame.iter() {
+                assert_eq!(L::try_get(&table, number).unwrap().0, PhysPageNum::new(frame.number));
+                assert!(contains(PhysPageNum::new(frame.number)));
+            }
+        }
+
+        // intermediate tables are reclaimed once every page is unmapped
+        for current in page {
+            let _ = L::try_unmap(&mut table, current);
+        }
+        assert!(table.table.is_empty());
+        assert_eq!(table.destroy(), 1);
+    }
+
+    #[test]
+    fn sv39() {
+        property::<Sv39>(39);
+    }
+
+    #[test]
+    fn sv48() {
+        property::<Sv48>(48);
+    }
+
+    #[test]
+    fn x86_64() {
+        property::<X86_64>(64);
+    }
+
+    #[test]
+    fn aarch64() {
+        property::<AArch64_4K>(8);
+    }
+
+    mod config {
+        /// 测试使用的页框数
+        pub const FRAME: usize = 128;
+    }
+}
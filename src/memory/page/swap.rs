@@ -0,0 +1,272 @@
+/*!
+页面置换
+
+换出的页表项 V 位被清除，其余权限位保留，页框号字段记录交换槽
+
+页表中的交换槽均来自由 init 安装的全局交换区：解除映射与销毁页表时释放交换槽，fork 前换入换出的页，
+按需分配的页框由 alloc 取得，页框不足时换出一页。交换区的锁在读写块设备时保持，交换区应使用不会阻塞线程的块设备
+
+# 函数
+init()
+
+access()
+
+alloc()
+
+# 结构体
+Swap
+*/
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::slice::{ from_raw_parts, from_raw_parts_mut };
+
+use crate::{
+    Allocator,
+    memory::{ Flag, Address, VirtPageNum },
+    peripheral::Block,
+};
+use super::{ Lib, Table, Error, frame::Frame };
+
+pub struct Swap {
+    disk: Box<dyn Block>,
+    /// 交换区的起始块号
+    start: usize,
+    /// 每个交换槽占用的块数
+    per: usize,
+    slot: Allocator,
+    /// 正在使用的交换槽数
+    used: usize,
+    /// CLOCK 指针，上次换出的页号
+    hand: VirtPageNum,
+}
+
+impl Swap {
+    /**
+    以 disk 上从块 start 开始、共 slot 页的区域作为交换区
+    */
+    pub fn new(disk: Box<dyn Block>, start: usize, slot: usize) -> Self {
+        let per = Address::address(1) / disk.block_size();
+
+        Self {
+            disk,
+            start,
+            per,
+            slot: Allocator::new(0, slot - 1).unwrap(),
+            used: 0,
+            hand: VirtPageNum::new(0),
+        }
+    }
+    /**
+    分配页框，页框不足时从 table 中换出一页
+    */
+    pub fn alloc<L: Lib + ?Sized>(&mut self, table: &mut Table) -> Result<Frame, Error> {
+        if let Some(frame) = Frame::try_new() {
+            return Ok(frame);
+        }
+
+        self.evict::<L>(table)?;
+        Frame::try_new().ok_or(Error::OutOfMemory)
+    }
+    /**
+    选择并换出一页，返回其页号；没有可换出的页时返回 Error::OutOfMemory
+    */
+    pub fn evict<L: Lib + ?Sized>(&mut self, table: &mut Table) -> Result<VirtPageNum, Error> {
+        let page = self.victim::<L>(table).ok_or(Error::OutOfMemory)?;
+        self.swap_out::<L>(table, page)?;
+
+        Ok(page)
+    }
+    /**
+    以 A、D 位选择换出的页（改进的 CLOCK）
+
+    从上次换出的页之后开始，先寻找未被访问且在交换区中有干净副本的页，再寻找未被访问的页，并清除经过的页的 A 位
+
+    只换出用户页；共享的页框、写时复制的页与大页不会被换出；调用者负责刷新 TLB
+    */
+    pub fn victim<L: Lib + ?Sized>(&mut self, table: &Table) -> Option<VirtPageNum> {
+        let candidate: Vec<VirtPageNum> = table.frame.range(self.hand..)
+            .filter(|(&page, _)| page != self.hand)
+            .chain(table.frame.range(..=self.hand))
+            .filter(|(page, frame)| Arc::strong_count(frame) == 1 && !table.cow.contains(page))
+            .filter(|(&page, _)| matches!(L::walk(table, page), Ok((entry, _)) if L::flag(entry).contains(Flag::U)))
+            .map(|(&page, _)| page)
+            .collect();
+
+        for _ in 0..2 {
+            // a clean page with a copy in the swap area needs no write back
+            for &page in candidate.iter() {
+                let flag = L::flag(L::walk(table, page).ok()?.0);
+                if !flag.intersects(Flag::A | Flag::D) && table.swap.contains_key(&page) {
+                    self.hand = page;
+                    return Some(page);
+                }
+            }
+
+            for &page in candidate.iter() {
+                let (entry, _) = L::walk(table, page).ok()?;
+                let flag = L::flag(entry);
+                if !flag.contains(Flag::A) {
+                    self.hand = page;
+                    return Some(page);
+                }
+                L::set_flag(entry, flag - Flag::A);
+            }
+        }
+
+        None
+    }
+    /**
+    将页写入交换区并释放其页框，干净且已有副本的页不再写入
+    */
+    pub fn swap_out<L: Lib + ?Sized>(&mut self, table: &mut Table, page_num: VirtPageNum) -> Result<(), Error> {
+        let (entry, level) = L::walk(table, page_num)?;
+        let flag = L::flag(entry);
+        let frame = table.frame.get(&page_num).ok_or(Error::NotMapped)?;
+        if !flag.is_leaf() || level != L::conf() - 1 || Arc::strong_count(frame) > 1 || table.cow.contains(&page_num) {
+            return Err(Error::PermissionDenied);
+        }
+        let frame_number = frame.number;
+
+        let slot = match table.swap.get(&page_num) {
+            Some(&slot) if !flag.contains(Flag::D) => slot,
+            Some(&slot) => {
+                self.write(slot, frame_number);
+                slot
+            },
+            None => {
+                let slot = self.slot.alloc().map_err(|_| Error::SwapFull)?;
+                self.used += 1;
+                self.write(slot, frame_number);
+                slot
+            },
+        };
+
+        *entry = L::new_entry(slot, flag - Flag::V - Flag::A - Flag::D);
+        table.swap.insert(page_num, slot);
+        table.frame.remove(&page_num);
+
+        Ok(())
+    }
+    /**
+    缺页时将换出的页读回，交换槽保留为该页的干净副本
+
+    页已驻留时不做任何操作，页未被换出时返回 Error::NotMapped
+    */
+    pub fn swap_in<L: Lib + ?Sized>(&mut self, table: &mut Table, page_num: VirtPageNum) -> Result<(), Error> {
+        let slot = *table.swap.get(&page_num).ok_or(Error::NotMapped)?;
+        if L::flag(L::walk(table, page_num)?.0).is_valid() {
+            return Ok(());
+        }
+
+        let frame = self.alloc::<L>(table)?;
+        self.read(slot, frame.number);
+
+        let (entry, _) = L::walk(table, page_num)?;
+        *entry = L::new_entry(frame.number, L::flag(entry) | Flag::V);
+        table.frame.insert(page_num, Arc::new(frame));
+
+        Ok(())
+    }
+    /**
+    页是否已被换出
+    */
+    pub fn is_swapped<L: Lib + ?Sized>(table: &Table, page_num: VirtPageNum) -> bool {
+        table.swap.contains_key(&page_num)
+            && matches!(L::walk(table, page_num), Ok((entry, _)) if !L::flag(entry).is_valid())
+    }
+    /**
+    正在使用的交换槽数
+    */
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.used
+    }
+    /**
+    释放交换槽
+    */
+    pub fn free(&mut self, slot: usize) {
+        self.slot.dealloc(slot);
+        self.used -= 1;
+    }
+    /**
+    换入所有换出的页并释放页表的所有交换槽，之后页表可以直接复制
+    */
+    pub fn resident<L: Lib + ?Sized>(&mut self, table: &mut Table) -> Result<(), Error> {
+        let page: Vec<VirtPageNum> = table.swap.keys().copied().collect();
+        for &page_num in page.iter() {
+            self.swap_in::<L>(table, page_num)?;
+        }
+        // swapping in may evict pages swapped in before
+        if page.iter().any(|&page_num| Self::is_swapped::<L>(table, page_num)) {
+            return Err(Error::OutOfMemory);
+        }
+
+        for (_, slot) in core::mem::take(&mut table.swap) {
+            self.free(slot);
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, slot: usize, frame_number: usize) {
+        let data = unsafe { from_raw_parts(Address::address(frame_number) as *const u8, Address::address(1)) };
+        self.disk.write_blocks(self.start + slot * self.per, data);
+    }
+
+    fn read(&mut self, slot: usize, frame_number: usize) {
+        let data = unsafe { from_raw_parts_mut(Address::address(frame_number) as *mut u8, Address::address(1)) };
+        self.disk.read_blocks(self.start + slot * self.per, data);
+    }
+}
+
+use spin::Mutex;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref SWAP: Mutex<Option<Swap>> = Mutex::new(None);
+}
+/**
+安装全局交换区，替换已有的交换区；已换出的页应先被换入或释放
+*/
+pub fn init(swap: Swap) {
+    *SWAP.lock() = Some(swap);
+}
+/**
+Access the global swap area.
+*/
+#[inline]
+pub fn access<F, V>(f: F) -> V
+where
+    F: FnOnce(&mut Option<Swap>) -> V,
+{
+    let mut mutex = SWAP.lock();
+    f(&mut mutex)
+}
+/**
+分配页框，页框不足且安装了交换区时从 table 中换出一页
+*/
+pub fn alloc<L: Lib + ?Sized>(table: &mut Table) -> Result<Frame, Error> {
+    if let Some(frame) = Frame::try_new() {
+        return Ok(frame);
+    }
+
+    access(|swap| match swap {
+        Some(swap) => swap.alloc::<L>(table),
+        None => Err(Error::OutOfMemory),
+    })
+}
+/**
+释放页表中页号为 page_num 的交换槽
+*/
+pub(super) fn free(table: &mut Table, page_num: VirtPageNum) {
+    if let Some(slot) = table.swap.remove(&page_num) {
+        access(|swap| swap.as_mut().expect("Swap area not installed.").free(slot));
+    }
+}
+/**
+通过全局交换区换入页表中页号为 page_num 的页，未安装交换区时返回 Error::NotMapped
+*/
+pub fn swap_in<L: Lib + ?Sized>(table: &mut Table, page_num: VirtPageNum) -> Result<(), Error> {
+    access(|swap| swap.as_mut().ok_or(Error::NotMapped)?.swap_in::<L>(table, page_num))
+}
@@ -1,6 +1,11 @@
-use crate::{ memory::AsRaw, peripheral::virtio::{ Hal, Result, Error, header::VirtIOHeader, queue::VirtQueue } };
+use crate::{ memory::AsRaw, peripheral::{ Block, plic, virtio::{ Hal, Result, Error, header::VirtIOHeader, queue::VirtQueue } } };
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use bitflags::*;
 use core::hint::spin_loop;
+use core::marker::PhantomData;
+use spin::Mutex;
 use volatile::Volatile;
 
 /// The virtio block device is a simple virtual block device (ie. disk).
@@ -10,7 +15,38 @@ use volatile::Volatile;
 pub struct VirtIOBlk<'a, H: Hal> {
     header: &'static mut VirtIOHeader,
     queue: VirtQueue<'a, H>,
-    _capacity: usize,
+    capacity: usize,
+    /// Threads waiting for requests in interrupt-driven mode, indexed by token.
+    waiting: BTreeMap<u16, Waiter>,
+}
+
+/// The scheduler interface used by the interrupt-driven mode.
+pub trait Dep {
+    /// Return the id of the current thread.
+    fn current() -> usize;
+    /// Block the current thread and switch to another one.
+    ///
+    /// A wake-up issued after the request has been submitted must not be lost, e.g.
+    /// [crate::concurrency::scheduler::Scheduler::block] returns immediately if the
+    /// thread has already been woken up.
+    fn block();
+    /// Wake up a blocked thread.
+    fn wake(tid: usize);
+    /// Forget a wake-up of the thread that was not consumed by [Dep::block()], called
+    /// after a request is completed, e.g. [crate::concurrency::scheduler::Scheduler::forget].
+    fn forget(tid: usize);
+    /// Disable interrupts of the current hart and return whether they were enabled.
+    ///
+    /// The device lock is also taken by the interrupt handler, so it is only held with
+    /// interrupts disabled.
+    fn interrupt_disable() -> bool;
+    /// Enable interrupts of the current hart again if `enabled`.
+    fn interrupt_restore(enabled: bool);
+}
+
+struct Waiter {
+    tid: usize,
+    done: bool,
 }
 
 impl<H: Hal> VirtIOBlk<'_, H> {
@@ -32,7 +68,8 @@ impl<H: Hal> VirtIOBlk<'_, H> {
         Ok(VirtIOBlk {
             header,
             queue,
-            _capacity: config.capacity.read() as usize,
+            capacity: config.capacity.read() as usize,
+            waiting: BTreeMap::new(),
         })
     }
 
@@ -128,6 +165,85 @@ impl<H: Hal> VirtIOBlk<'_, H> {
         }
     }
 
+    /// Read contiguous blocks starting from `block_id`.
+    ///
+    /// The buffer is split into requests of at most `REQ_BLOCKS` blocks, and as many
+    /// requests as the queue can hold are submitted with a single notification.
+    pub fn read_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> Result {
+        assert_eq!(buf.len() % BLK_SIZE, 0);
+        let mut sector = block_id;
+        for batch in buf.chunks_mut(self.batch_size() * REQ_BLOCKS * BLK_SIZE) {
+            let number = batch.len().div_ceil(REQ_BLOCKS * BLK_SIZE);
+            let req: Vec<BlkReq> = (0..number)
+                .map(|i| BlkReq {
+                    type_: ReqType::In,
+                    reserved: 0,
+                    sector: (sector + i * REQ_BLOCKS) as u64,
+                })
+                .collect();
+            let mut resp: Vec<BlkResp> = (0..number).map(|_| BlkResp::default()).collect();
+
+            for ((chunk, req), resp) in batch.chunks_mut(REQ_BLOCKS * BLK_SIZE).zip(req.iter()).zip(resp.iter_mut()) {
+                self.queue.add(&[req.as_raw()], &[chunk, resp.as_raw_mut()])?;
+            }
+            self.header.notify(0);
+            self.wait(&resp)?;
+
+            sector += batch.len() / BLK_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Write contiguous blocks starting from `block_id`.
+    ///
+    /// See also [VirtIOBlk::read_blocks()].
+    pub fn write_blocks(&mut self, block_id: usize, buf: &[u8]) -> Result {
+        assert_eq!(buf.len() % BLK_SIZE, 0);
+        let mut sector = block_id;
+        for batch in buf.chunks(self.batch_size() * REQ_BLOCKS * BLK_SIZE) {
+            let number = batch.len().div_ceil(REQ_BLOCKS * BLK_SIZE);
+            let req: Vec<BlkReq> = (0..number)
+                .map(|i| BlkReq {
+                    type_: ReqType::Out,
+                    reserved: 0,
+                    sector: (sector + i * REQ_BLOCKS) as u64,
+                })
+                .collect();
+            let mut resp: Vec<BlkResp> = (0..number).map(|_| BlkResp::default()).collect();
+
+            for ((chunk, req), resp) in batch.chunks(REQ_BLOCKS * BLK_SIZE).zip(req.iter()).zip(resp.iter_mut()) {
+                self.queue.add(&[req.as_raw(), chunk], &[resp.as_raw_mut()])?;
+            }
+            self.header.notify(0);
+            self.wait(&resp)?;
+
+            sector += batch.len() / BLK_SIZE;
+        }
+        Ok(())
+    }
+
+    /// The number of requests which can be submitted at once, each one takes 3 descriptors.
+    fn batch_size(&self) -> usize {
+        (self.queue.available_desc() / 3).max(1)
+    }
+
+    /// Wait until all submitted requests are completed.
+    fn wait(&mut self, resp: &[BlkResp]) -> Result {
+        let mut completed = 0;
+        while completed < resp.len() {
+            while !self.queue.can_pop() {
+                spin_loop();
+            }
+            self.queue.pop_used()?;
+            completed += 1;
+        }
+        if resp.iter().all(|resp| resp.status == RespStatus::Ok) {
+            Ok(())
+        } else {
+            Err(Error::IoError)
+        }
+    }
+
     //// Write a block in a non-blocking way which means that it returns immediately.
     ///
     /// # Arguments
@@ -169,6 +285,214 @@ impl<H: Hal> VirtIOBlk<'_, H> {
         self.queue.pop_used().map(|p| p.0)
     }
 
+    /// Submit a read request in interrupt-driven mode, the thread `tid` is woken up
+    /// by [VirtIOBlk::handle_interrupt()] when the request is completed.
+    ///
+    /// # Safety
+    ///
+    /// See also [VirtIOBlk::read_block_nb()].
+    pub unsafe fn read_block_irq(
+        &mut self,
+        block_id: usize,
+        buf: &mut [u8],
+        resp: &mut BlkResp,
+        tid: usize,
+    ) -> Result<u16> {
+        let token = self.read_block_nb(block_id, buf, resp)?;
+        self.waiting.insert(token, Waiter { tid, done: false });
+        Ok(token)
+    }
+
+    /// Submit a write request in interrupt-driven mode.
+    ///
+    /// # Safety
+    ///
+    /// See also [VirtIOBlk::read_block_nb()].
+    pub unsafe fn write_block_irq(
+        &mut self,
+        block_id: usize,
+        buf: &[u8],
+        resp: &mut BlkResp,
+        tid: usize,
+    ) -> Result<u16> {
+        let token = self.write_block_nb(block_id, buf, resp)?;
+        self.waiting.insert(token, Waiter { tid, done: false });
+        Ok(token)
+    }
+
+    /// Handle the external interrupt of the device: acknowledge it, match the tokens
+    /// of completed requests to the waiting threads and wake them up.
+    ///
+    /// Return whether the interrupt came from this device.
+    pub fn handle_interrupt<D: Dep>(&mut self) -> bool {
+        if !self.ack_interrupt() {
+            return false;
+        }
+        while let Ok(token) = self.pop_used() {
+            if let Some(waiter) = self.waiting.get_mut(&token) {
+                waiter.done = true;
+                D::wake(waiter.tid);
+            }
+        }
+        true
+    }
+
+    /// Return whether the request identified by `token` is completed, and forget it if so.
+    pub fn complete(&mut self, token: u16) -> bool {
+        match self.waiting.get(&token) {
+            Some(waiter) if waiter.done => {
+                self.waiting.remove(&token);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Call `f` with the lock of `blk` held and interrupts disabled, so that the
+    /// interrupt handler cannot spin on the lock held by the thread it interrupted.
+    pub fn locked<D: Dep, V>(blk: &Mutex<Self>, f: impl FnOnce(&mut Self) -> V) -> V {
+        let enabled = D::interrupt_disable();
+        let value = f(&mut blk.lock());
+        D::interrupt_restore(enabled);
+        value
+    }
+
+    /// Wait until the request identified by `token` is completed.
+    fn wait_blocking<D: Dep>(blk: &Mutex<Self>, token: u16, tid: usize) {
+        while !Self::locked::<D, _>(blk, |blk| blk.complete(token)) {
+            D::block();
+        }
+        // the request may complete before the thread blocks
+        D::forget(tid);
+    }
+
+    /// Read a block, blocking the current thread instead of spinning until the
+    /// device raises an interrupt.
+    ///
+    /// The lock of `blk` is released while waiting, so that the interrupt handler can
+    /// call [VirtIOBlk::handle_interrupt()].
+    pub fn read_block_blocking<D: Dep>(blk: &Mutex<Self>, block_id: usize, buf: &mut [u8]) -> Result {
+        let mut resp = BlkResp::default();
+        let tid = D::current();
+        // buf and resp are not accessed until the request is completed
+        let token = Self::locked::<D, _>(blk, |blk| unsafe { blk.read_block_irq(block_id, buf, &mut resp, tid) })?;
+        Self::wait_blocking::<D>(blk, token, tid);
+        match resp.status {
+            RespStatus::Ok => Ok(()),
+            _ => Err(Error::IoError),
+        }
+    }
+
+    /// Write a block, blocking the current thread instead of spinning.
+    ///
+    /// See also [VirtIOBlk::read_block_blocking()].
+    pub fn write_block_blocking<D: Dep>(blk: &Mutex<Self>, block_id: usize, buf: &[u8]) -> Result {
+        let mut resp = BlkResp::default();
+        let tid = D::current();
+        let token = Self::locked::<D, _>(blk, |blk| unsafe { blk.write_block_irq(block_id, buf, &mut resp, tid) })?;
+        Self::wait_blocking::<D>(blk, token, tid);
+        match resp.status {
+            RespStatus::Ok => Ok(()),
+            _ => Err(Error::IoError),
+        }
+    }
+
+    /// Read contiguous blocks starting from `block_id`, blocking the current thread.
+    ///
+    /// Like [VirtIOBlk::read_blocks()], as many requests as the queue can hold are
+    /// submitted at once; the size of each batch is decided with the lock held.
+    pub fn read_blocks_blocking<D: Dep>(blk: &Mutex<Self>, block_id: usize, buf: &mut [u8]) -> Result {
+        assert_eq!(buf.len() % BLK_SIZE, 0);
+        let tid = D::current();
+        let mut sector = block_id;
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let mut req = Vec::new();
+            let mut resp = Vec::new();
+            // the buffers are not accessed until the requests are completed
+            let (token, len) = Self::locked::<D, _>(blk, |blk| -> Result<(Vec<u16>, usize)> {
+                let number = blk.batch_size().min(rest.len().div_ceil(REQ_BLOCKS * BLK_SIZE));
+                let len = rest.len().min(number * REQ_BLOCKS * BLK_SIZE);
+                req = (0..number)
+                    .map(|i| BlkReq {
+                        type_: ReqType::In,
+                        reserved: 0,
+                        sector: (sector + i * REQ_BLOCKS) as u64,
+                    })
+                    .collect();
+                resp = (0..number).map(|_| BlkResp::default()).collect();
+
+                let mut token = Vec::with_capacity(number);
+                for ((chunk, req), resp) in rest[..len].chunks_mut(REQ_BLOCKS * BLK_SIZE).zip(req.iter()).zip(resp.iter_mut()) {
+                    token.push(blk.queue.add(&[req.as_raw()], &[chunk, resp.as_raw_mut()])?);
+                }
+                blk.irq_submit(&token, tid);
+                Ok((token, len))
+            })?;
+            for token in token {
+                Self::wait_blocking::<D>(blk, token, tid);
+            }
+            if resp.iter().any(|resp| resp.status != RespStatus::Ok) {
+                return Err(Error::IoError);
+            }
+
+            sector += len / BLK_SIZE;
+            rest = &mut rest[len..];
+        }
+        Ok(())
+    }
+
+    /// Write contiguous blocks starting from `block_id`, blocking the current thread.
+    ///
+    /// See also [VirtIOBlk::read_blocks_blocking()].
+    pub fn write_blocks_blocking<D: Dep>(blk: &Mutex<Self>, block_id: usize, buf: &[u8]) -> Result {
+        assert_eq!(buf.len() % BLK_SIZE, 0);
+        let tid = D::current();
+        let mut sector = block_id;
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let mut req = Vec::new();
+            let mut resp = Vec::new();
+            let (token, len) = Self::locked::<D, _>(blk, |blk| -> Result<(Vec<u16>, usize)> {
+                let number = blk.batch_size().min(rest.len().div_ceil(REQ_BLOCKS * BLK_SIZE));
+                let len = rest.len().min(number * REQ_BLOCKS * BLK_SIZE);
+                req = (0..number)
+                    .map(|i| BlkReq {
+                        type_: ReqType::Out,
+                        reserved: 0,
+                        sector: (sector + i * REQ_BLOCKS) as u64,
+                    })
+                    .collect();
+                resp = (0..number).map(|_| BlkResp::default()).collect();
+
+                let mut token = Vec::with_capacity(number);
+                for ((chunk, req), resp) in rest[..len].chunks(REQ_BLOCKS * BLK_SIZE).zip(req.iter()).zip(resp.iter_mut()) {
+                    token.push(blk.queue.add(&[req.as_raw(), chunk], &[resp.as_raw_mut()])?);
+                }
+                blk.irq_submit(&token, tid);
+                Ok((token, len))
+            })?;
+            for token in token {
+                Self::wait_blocking::<D>(blk, token, tid);
+            }
+            if resp.iter().any(|resp| resp.status != RespStatus::Ok) {
+                return Err(Error::IoError);
+            }
+
+            sector += len / BLK_SIZE;
+            rest = &rest[len..];
+        }
+        Ok(())
+    }
+
+    /// Record the thread `tid` waiting for the requests `token` and notify the device.
+    fn irq_submit(&mut self, token: &[u16], tid: usize) {
+        for &token in token {
+            self.waiting.insert(token, Waiter { tid, done: false });
+        }
+        self.header.notify(0);
+    }
+
     /// Return size of its VirtQueue.
     /// It can be used to tell the caller how many channels he should monitor on.
     pub fn virt_queue_size(&self) -> u16 {
@@ -176,6 +500,81 @@ impl<H: Hal> VirtIOBlk<'_, H> {
     }
 }
 
+impl<H: Hal + Send + Sync + 'static> Block for VirtIOBlk<'static, H> {
+    fn read(&mut self, address: usize, cache: &mut [u8]) {
+        self.read_block(address, cache).expect("Error when reading VirtIOBlk!");
+    }
+
+    fn write(&mut self, address: usize, cache: &[u8]) {
+        self.write_block(address, cache).expect("Error when writing VirtIOBlk!");
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn read_blocks(&mut self, start: usize, cache: &mut [u8]) {
+        VirtIOBlk::read_blocks(self, start, cache).expect("Error when reading VirtIOBlk!");
+    }
+
+    fn write_blocks(&mut self, start: usize, cache: &[u8]) {
+        VirtIOBlk::write_blocks(self, start, cache).expect("Error when writing VirtIOBlk!");
+    }
+}
+
+/// A virtio block device shared with the interrupt handler, its requests block the
+/// current thread until completed.
+///
+/// The interrupt handler should call [VirtIOBlk::handle_interrupt()] on the same device,
+/// e.g. through [IrqBlk::register()].
+pub struct IrqBlk<H: Hal + 'static, D: Dep> {
+    blk: Arc<Mutex<VirtIOBlk<'static, H>>>,
+    _phantom: PhantomData<fn() -> D>,
+}
+
+impl<H: Hal + 'static, D: Dep> IrqBlk<H, D> {
+    pub fn new(blk: Arc<Mutex<VirtIOBlk<'static, H>>>) -> Self {
+        Self {
+            blk,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<H: Hal + Send + Sync + 'static, D: Dep + 'static> IrqBlk<H, D> {
+    /// Register [VirtIOBlk::handle_interrupt()] as the handler of the PLIC interrupt
+    /// source `interrupt`, called by [plic::Handler::dispatch()].
+    pub fn register(&self, interrupt: usize) {
+        let blk = self.blk.clone();
+        plic::Handler::register(interrupt, Arc::new(move || {
+            // interrupts are disabled in the handler
+            blk.lock().handle_interrupt::<D>();
+        }));
+    }
+}
+
+impl<H: Hal + Send + Sync + 'static, D: Dep + 'static> Block for IrqBlk<H, D> {
+    fn read(&mut self, address: usize, cache: &mut [u8]) {
+        VirtIOBlk::read_block_blocking::<D>(&self.blk, address, cache).expect("Error when reading VirtIOBlk!");
+    }
+
+    fn write(&mut self, address: usize, cache: &[u8]) {
+        VirtIOBlk::write_block_blocking::<D>(&self.blk, address, cache).expect("Error when writing VirtIOBlk!");
+    }
+
+    fn capacity(&self) -> usize {
+        VirtIOBlk::locked::<D, _>(&self.blk, |blk| blk.capacity)
+    }
+
+    fn read_blocks(&mut self, start: usize, cache: &mut [u8]) {
+        VirtIOBlk::read_blocks_blocking::<D>(&self.blk, start, cache).expect("Error when reading VirtIOBlk!");
+    }
+
+    fn write_blocks(&mut self, start: usize, cache: &[u8]) {
+        VirtIOBlk::write_blocks_blocking::<D>(&self.blk, start, cache).expect("Error when writing VirtIOBlk!");
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 struct BlkConfig {
@@ -250,6 +649,9 @@ impl Default for BlkResp {
 
 const BLK_SIZE: usize = 512;
 
+/// The maximum number of blocks in a single request.
+const REQ_BLOCKS: usize = 8;
+
 bitflags! {
     struct BlkFeature: u64 {
         /// Device supports request barriers. (legacy)
@@ -1,8 +1,11 @@
 pub mod instance;
 pub mod virtio;
 pub mod plic;
+pub mod partition;
 
 use core::any::Any;
+use alloc::sync::Arc;
+use spin::Mutex;
 
 pub trait Lib {
     /**
@@ -19,6 +22,107 @@ for Arc, can not be mut
 pub trait Block: Send + Sync + Any {
     fn read(&mut self, address: usize, cache: &mut [u8]);
     fn write(&mut self, address: usize, cache: &[u8]);
+    /**
+    块的大小，单位：字节
+    */
+    fn block_size(&self) -> usize {
+        512
+    }
+    /**
+    块的数量，未知时为 usize::MAX
+    */
+    fn capacity(&self) -> usize {
+        usize::MAX
+    }
+    /**
+    读取从 start 开始的连续多个块，cache 的长度应为块大小的整数倍
+
+    设备支持批量请求时应重新实现
+    */
+    fn read_blocks(&mut self, start: usize, cache: &mut [u8]) {
+        let size = self.block_size();
+        for (i, block) in cache.chunks_mut(size).enumerate() {
+            self.read(start + i, block);
+        }
+    }
+    /**
+    写入从 start 开始的连续多个块，cache 的长度应为块大小的整数倍
+
+    设备支持批量请求时应重新实现
+    */
+    fn write_blocks(&mut self, start: usize, cache: &[u8]) {
+        let size = self.block_size();
+        for (i, block) in cache.chunks(size).enumerate() {
+            self.write(start + i, block);
+        }
+    }
+    /**
+    将从 start 开始的连续多个块分别读入多个缓冲区，每个缓冲区的长度应为块大小的整数倍
+    */
+    fn read_vectored(&mut self, start: usize, cache: &mut [&mut [u8]]) {
+        let size = self.block_size();
+        let mut current = start;
+        for buf in cache.iter_mut() {
+            self.read_blocks(current, buf);
+            current += buf.len() / size;
+        }
+    }
+    /**
+    将多个缓冲区写入从 start 开始的连续多个块，每个缓冲区的长度应为块大小的整数倍
+    */
+    fn write_vectored(&mut self, start: usize, cache: &[&[u8]]) {
+        let size = self.block_size();
+        let mut current = start;
+        for buf in cache.iter() {
+            self.write_blocks(current, buf);
+            current += buf.len() / size;
+        }
+    }
+    /**
+    将缓存的数据写回设备
+    */
+    fn sync(&mut self) {}
+}
+
+/**
+共享的块设备
+*/
+impl<T: Block + ?Sized> Block for Arc<Mutex<T>> {
+    fn read(&mut self, address: usize, cache: &mut [u8]) {
+        self.lock().read(address, cache);
+    }
+
+    fn write(&mut self, address: usize, cache: &[u8]) {
+        self.lock().write(address, cache);
+    }
+
+    fn block_size(&self) -> usize {
+        self.lock().block_size()
+    }
+
+    fn capacity(&self) -> usize {
+        self.lock().capacity()
+    }
+
+    fn read_blocks(&mut self, start: usize, cache: &mut [u8]) {
+        self.lock().read_blocks(start, cache);
+    }
+
+    fn write_blocks(&mut self, start: usize, cache: &[u8]) {
+        self.lock().write_blocks(start, cache);
+    }
+
+    fn read_vectored(&mut self, start: usize, cache: &mut [&mut [u8]]) {
+        self.lock().read_vectored(start, cache);
+    }
+
+    fn write_vectored(&mut self, start: usize, cache: &[&[u8]]) {
+        self.lock().write_vectored(start, cache);
+    }
+
+    fn sync(&mut self) {
+        self.lock().sync();
+    }
 }
 
 pub trait Character {
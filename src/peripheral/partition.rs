@@ -0,0 +1,443 @@
+/*!
+分区表
+
+解析 MBR（包括扩展分区）和 GPT 分区表，将块设备划分为多个分区，每个分区都是一个块设备
+
+# 用法
+```text
+let disk: Arc<Mutex<dyn Block>> = ...;
+let partition = partition::open(disk)?;
+let root: Arc<Mutex<dyn Block>> = Arc::new(Mutex::new(partition.into_iter().nth(1).unwrap()));
+```
+*/
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::Block;
+
+/**
+分区，块号相对于分区起始位置
+*/
+pub struct Partition {
+    disk: Arc<Mutex<dyn Block>>,
+    entry: Entry,
+}
+
+impl Partition {
+    pub fn new(disk: Arc<Mutex<dyn Block>>, entry: Entry) -> Self {
+        Self { disk, entry }
+    }
+
+    #[inline]
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+    /**
+    检查 \[address, address + number) 位于分区内，返回在磁盘上的块号
+    */
+    fn translate(&self, address: usize, number: usize) -> usize {
+        assert!(
+            address.checked_add(number).is_some_and(|end| end <= self.entry.len),
+            "Block {} out of partition, whose length is {}.", address + number, self.entry.len
+        );
+
+        self.entry.start + address
+    }
+}
+
+impl Block for Partition {
+    fn read(&mut self, address: usize, cache: &mut [u8]) {
+        let address = self.translate(address, 1);
+        self.disk.lock().read(address, cache);
+    }
+
+    fn write(&mut self, address: usize, cache: &[u8]) {
+        let address = self.translate(address, 1);
+        self.disk.lock().write(address, cache);
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.lock().block_size()
+    }
+
+    fn capacity(&self) -> usize {
+        self.entry.len
+    }
+
+    fn read_blocks(&mut self, start: usize, cache: &mut [u8]) {
+        let mut disk = self.disk.lock();
+        let start = self.translate(start, cache.len() / disk.block_size());
+        disk.read_blocks(start, cache);
+    }
+
+    fn write_blocks(&mut self, start: usize, cache: &[u8]) {
+        let mut disk = self.disk.lock();
+        let start = self.translate(start, cache.len() / disk.block_size());
+        disk.write_blocks(start, cache);
+    }
+
+    fn sync(&mut self) {
+        self.disk.lock().sync();
+    }
+}
+
+/**
+分区表项
+
+start、len 的单位为块
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub kind: Kind,
+    pub start: usize,
+    pub len: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// 分区类型
+    Mbr(u8),
+    /// 分区类型 GUID，分区名称（非 ASCII 字符替换为 ?）
+    Gpt([u8; 16], Vec<u8>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// 不存在分区表
+    NoTable,
+    /// 分区表损坏
+    Corrupted,
+    /// 分区超出磁盘范围
+    OutOfDisk,
+}
+
+/**
+解析分区表，返回所有分区
+*/
+pub fn open(disk: Arc<Mutex<dyn Block>>) -> Result<Vec<Partition>, Error> {
+    let entry = parse(&mut *disk.lock())?;
+
+    Ok(entry.into_iter().map(|entry| Partition::new(disk.clone(), entry)).collect())
+}
+/**
+解析分区表，保护性 MBR 之后存在 GPT 时解析 GPT
+*/
+pub fn parse(disk: &mut dyn Block) -> Result<Vec<Entry>, Error> {
+    let mut sector = vec![0u8; disk.block_size()];
+    disk.read(0, &mut sector);
+
+    if sector.len() < 512 || sector[510..512] != mbr::SIGNATURE {
+        return Err(Error::NoTable);
+    }
+
+    let primary = mbr::entries(&sector);
+    if primary.iter().any(|&(kind, _, _)| kind == mbr::PROTECTIVE) {
+        return gpt::parse(disk);
+    }
+
+    let mut entry = Vec::new();
+    for (kind, start, len) in primary {
+        if mbr::EXTENDED.contains(&kind) {
+            mbr::logical(disk, start, &mut entry)?;
+        } else {
+            entry.push(Entry { kind: Kind::Mbr(kind), start, len });
+        }
+    }
+
+    check(disk, entry)
+}
+
+fn check(disk: &dyn Block, entry: Vec<Entry>) -> Result<Vec<Entry>, Error> {
+    let capacity = disk.capacity();
+    for e in entry.iter() {
+        if e.start.checked_add(e.len).is_none_or(|end| end > capacity) {
+            return Err(Error::OutOfDisk);
+        }
+    }
+
+    Ok(entry)
+}
+
+mod mbr {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::{ Block, Entry, Error, Kind };
+
+    pub const SIGNATURE: [u8; 2] = [0x55, 0xaa];
+    /// 保护性 MBR 的分区类型
+    pub const PROTECTIVE: u8 = 0xee;
+    /// 扩展分区的分区类型（CHS、LBA）
+    pub const EXTENDED: [u8; 2] = [0x05, 0x0f];
+    /// 扩展分区链表的最大长度，防止成环
+    const LOGICAL_LIMIT: usize = 128;
+    const TABLE: usize = 446;
+
+    /**
+    返回非空的表项：(kind, start, len)
+    */
+    pub fn entries(sector: &[u8]) -> Vec<(u8, usize, usize)> {
+        (0..4)
+            .map(|i| &sector[TABLE + i * 16..TABLE + (i + 1) * 16])
+            .filter(|raw| raw[4] != 0)
+            .map(|raw| {
+                let start = u32::from_le_bytes(raw[8..12].try_into().unwrap()) as usize;
+                let len = u32::from_le_bytes(raw[12..16].try_into().unwrap()) as usize;
+                (raw[4], start, len)
+            })
+            .collect()
+    }
+    /**
+    遍历扩展分区中的 EBR 链表
+
+    EBR 中第一项的起始位置相对于该 EBR，第二项的起始位置相对于扩展分区
+    */
+    pub fn logical(disk: &mut dyn Block, extended: usize, entry: &mut Vec<Entry>) -> Result<(), Error> {
+        let mut sector = vec![0u8; disk.block_size()];
+        let mut current = extended;
+        if sector.len() < 512 {
+            return Err(Error::Corrupted);
+        }
+
+        for _ in 0..LOGICAL_LIMIT {
+            if current >= disk.capacity() {
+                return Err(Error::OutOfDisk);
+            }
+            disk.read(current, &mut sector);
+            if sector[510..512] != SIGNATURE {
+                return Err(Error::Corrupted);
+            }
+
+            let ebr = entries(&sector);
+            if let Some(&(kind, start, len)) = ebr.first() {
+                entry.push(Entry { kind: Kind::Mbr(kind), start: current + start, len });
+            }
+            match ebr.get(1) {
+                Some(&(_, next, _)) => current = extended + next,
+                None => return Ok(()),
+            }
+        }
+
+        Err(Error::Corrupted)
+    }
+}
+
+mod gpt {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::{ Block, Entry, Error, Kind, check };
+
+    const SIGNATURE: &[u8; 8] = b"EFI PART";
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+    /**
+    主 GPT 头位于块 1
+    */
+    pub fn parse(disk: &mut dyn Block) -> Result<Vec<Entry>, Error> {
+        let size = disk.block_size();
+        let mut header = vec![0u8; size];
+        disk.read(1, &mut header);
+
+        if &header[0..8] != SIGNATURE {
+            return Err(Error::NoTable);
+        }
+        let header_size = u32_at(&header, 12) as usize;
+        if !(92..=size).contains(&header_size) {
+            return Err(Error::Corrupted);
+        }
+        let mut raw = header[..header_size].to_vec();
+        raw[16..20].fill(0);
+        if crc32(&raw) != u32_at(&header, 16) {
+            return Err(Error::Corrupted);
+        }
+
+        let table = u64_at(&header, 72) as usize;
+        let number = u32_at(&header, 80) as usize;
+        let entry_size = u32_at(&header, 84) as usize;
+        if entry_size < 128 || number.checked_mul(entry_size).is_none_or(|len| len > size * 4096) {
+            return Err(Error::Corrupted);
+        }
+
+        let blocks = (number * entry_size).div_ceil(size);
+        if table.checked_add(blocks).is_none_or(|end| end > disk.capacity()) {
+            return Err(Error::OutOfDisk);
+        }
+        let mut array = vec![0u8; blocks * size];
+        disk.read_blocks(table, &mut array);
+        if crc32(&array[..number * entry_size]) != u32_at(&header, 88) {
+            return Err(Error::Corrupted);
+        }
+
+        let mut entry = Vec::new();
+        for raw in array[..number * entry_size].chunks(entry_size) {
+            let kind: [u8; 16] = raw[0..16].try_into().unwrap();
+            if kind == [0; 16] {
+                continue;
+            }
+
+            let first = u64_at(raw, 32) as usize;
+            let last = u64_at(raw, 40) as usize;
+            if last < first {
+                return Err(Error::Corrupted);
+            }
+            let name = raw[56..128]
+                .chunks(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0)
+                .map(|c| if c < 0x80 { c as u8 } else { b'?' })
+                .collect();
+
+            entry.push(Entry { kind: Kind::Gpt(kind, name), start: first, len: last - first + 1 });
+        }
+
+        check(disk, entry)
+    }
+
+    /**
+    CRC-32（IEEE 802.3）
+    */
+    pub fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+
+        !crc
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use spin::Mutex;
+
+    use crate::peripheral::Block;
+    use super::{ gpt::crc32, open, parse, Entry, Error, Kind };
+
+    struct Disk(Vec<u8>);
+
+    impl Block for Disk {
+        fn read(&mut self, address: usize, cache: &mut [u8]) {
+            cache.copy_from_slice(&self.0[address * 512..(address + 1) * 512]);
+        }
+
+        fn write(&mut self, address: usize, cache: &[u8]) {
+            self.0[address * 512..(address + 1) * 512].copy_from_slice(cache);
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len() / 512
+        }
+    }
+
+    fn mbr_entry(sector: &mut [u8], index: usize, kind: u8, start: u32, len: u32) {
+        let raw = &mut sector[446 + index * 16..446 + (index + 1) * 16];
+        raw[4] = kind;
+        raw[8..12].copy_from_slice(&start.to_le_bytes());
+        raw[12..16].copy_from_slice(&len.to_le_bytes());
+    }
+
+    fn signature(sector: &mut [u8]) {
+        sector[510] = 0x55;
+        sector[511] = 0xaa;
+    }
+
+    #[test]
+    fn mbr() {
+        let mut disk = Disk(vec![0; 128 * 512]);
+        assert_eq!(parse(&mut disk), Err(Error::NoTable));
+
+        let image = &mut disk.0;
+        mbr_entry(&mut image[..512], 0, 0x0c, 2, 30);
+        mbr_entry(&mut image[..512], 1, 0x05, 40, 80);
+        signature(&mut image[..512]);
+        // two logical partitions
+        mbr_entry(&mut image[40 * 512..41 * 512], 0, 0x83, 1, 9);
+        mbr_entry(&mut image[40 * 512..41 * 512], 1, 0x05, 20, 20);
+        signature(&mut image[40 * 512..41 * 512]);
+        mbr_entry(&mut image[60 * 512..61 * 512], 0, 0x83, 2, 10);
+        signature(&mut image[60 * 512..61 * 512]);
+
+        assert_eq!(parse(&mut disk).unwrap(), vec![
+            Entry { kind: Kind::Mbr(0x0c), start: 2, len: 30 },
+            Entry { kind: Kind::Mbr(0x83), start: 41, len: 9 },
+            Entry { kind: Kind::Mbr(0x83), start: 62, len: 10 },
+        ]);
+
+        mbr_entry(&mut disk.0[..512], 2, 0x83, 100, 100);
+        assert_eq!(parse(&mut disk), Err(Error::OutOfDisk));
+    }
+
+    #[test]
+    fn gpt() {
+        let mut image = vec![0u8; 128 * 512];
+        mbr_entry(&mut image[..512], 0, 0xee, 1, 127);
+        signature(&mut image[..512]);
+
+        // 4 entries of 128 bytes in block 2
+        let array = &mut image[2 * 512..3 * 512];
+        array[0..16].copy_from_slice(&[1; 16]);
+        array[32..40].copy_from_slice(&34u64.to_le_bytes());
+        array[40..48].copy_from_slice(&63u64.to_le_bytes());
+        for (i, c) in "boot".encode_utf16().enumerate() {
+            array[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        array[128..144].copy_from_slice(&[2; 16]);
+        array[160..168].copy_from_slice(&64u64.to_le_bytes());
+        array[168..176].copy_from_slice(&126u64.to_le_bytes());
+        let array_crc = crc32(array);
+
+        let header = &mut image[512..1024];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&array_crc.to_le_bytes());
+        let header_crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+
+        let disk: Arc<Mutex<dyn Block>> = Arc::new(Mutex::new(Disk(image)));
+        let mut partition = open(disk.clone()).unwrap();
+        assert_eq!(partition.len(), 2);
+        assert_eq!(partition[0].entry(), &Entry { kind: Kind::Gpt([1; 16], b"boot".to_vec()), start: 34, len: 30 });
+        assert_eq!(partition[1].capacity(), 63);
+
+        // blocks are relative to the partition
+        let data = [7u8; 1024];
+        partition[1].write_blocks(1, &data);
+        let mut block = [0u8; 512];
+        disk.lock().read(65, &mut block);
+        assert_eq!(block, [7u8; 512]);
+
+        // corrupted entry array
+        disk.lock().write(2, &[0u8; 512]);
+        assert_eq!(parse(&mut *disk.lock()).err(), Some(Error::Corrupted));
+    }
+
+    #[test]
+    #[should_panic]
+    fn bound() {
+        let disk: Arc<Mutex<dyn Block>> = Arc::new(Mutex::new(Disk(vec![0; 8 * 512])));
+        let mut partition = super::Partition::new(disk, Entry { kind: Kind::Mbr(0x83), start: 2, len: 4 });
+
+        let mut block = [0u8; 512];
+        partition.read(4, &mut block);
+    }
+}
@@ -77,6 +77,55 @@ impl Handler {
         }
     }
 
+    /**
+    获取 hart 下一个待处理的中断 id，0 表示无中断
+    */
+    pub fn claim(hart: usize) -> usize {
+        if let Some(handler) = HANDLER.lock().as_mut() {
+            unsafe { (&handler.claim_complete[hart] as *const u32).read_volatile() as usize }
+        } else {
+            panic!("PLIC hasn't been initialized!")
+        }
+    }
+    /**
+    通知 PLIC 中断处理完成
+    */
+    pub fn complete(hart: usize, interrupt: usize) {
+        if let Some(handler) = HANDLER.lock().as_mut() {
+            unsafe { (&mut handler.claim_complete[hart] as *mut u32).write_volatile(interrupt as u32) }
+        } else {
+            panic!("PLIC hasn't been initialized!")
+        }
+    }
+
+    /**
+    注册中断源 interrupt 的处理函数，替换已有的处理函数
+    */
+    pub fn register(interrupt: usize, handler: Arc<dyn Fn() + Send + Sync>) {
+        SOURCE.lock().insert(interrupt, handler);
+    }
+    /**
+    认领 hart 的下一个中断，调用其处理函数后通知 PLIC 处理完成
+
+    没有待处理的中断时返回 false
+    */
+    pub fn dispatch(hart: usize) -> bool {
+        let interrupt = Self::claim(hart);
+        if interrupt == 0 {
+            return false;
+        }
+
+        // the handler may register other handlers
+        let handler = SOURCE.lock().get(&interrupt).cloned();
+        match handler {
+            Some(handler) => handler(),
+            None => log::warn!("Interrupt {} has no handler.", interrupt),
+        }
+        Self::complete(hart, interrupt);
+
+        true
+    }
+
     pub fn threshold(hart: usize, priority: u32) {
         assert!(priority < 8, "优先级别范围为 [0, 7].");
 
@@ -88,8 +137,44 @@ impl Handler {
     }
 }
 
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use lazy_static::lazy_static;
 use spin::Mutex;
 lazy_static! {
     static ref HANDLER: Mutex<Option<Handler>> = Mutex::new(None);
+    /// 各中断源的处理函数
+    static ref SOURCE: Mutex<BTreeMap<usize, Arc<dyn Fn() + Send + Sync>>> = Mutex::new(BTreeMap::new());
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use core::sync::atomic::{ AtomicUsize, Ordering };
+    use super::Handler;
+
+    #[test]
+    fn dispatch() {
+        // priority, enable, threshold and claim/complete registers of two harts
+        let register: &'static mut [u32] = vec![0u32; 0x200010 / 4].leak();
+        let claim = register.as_mut_ptr() as usize + 0x200004;
+        unsafe { Handler::init(register.as_mut_ptr() as usize) };
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        Handler::register(3, Arc::new(move || { counter.fetch_add(1, Ordering::Relaxed); }));
+
+        assert!(!Handler::dispatch(0));
+        unsafe { (claim as *mut u32).write_volatile(3) };
+        assert!(Handler::dispatch(0));
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        // completed with the claimed id
+        assert_eq!(unsafe { (claim as *const u32).read_volatile() }, 3);
+
+        // no handler
+        unsafe { (claim as *mut u32).write_volatile(5) };
+        assert!(Handler::dispatch(0));
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
 }
\ No newline at end of file
@@ -1,66 +1,59 @@
 use super::*;
 use core::marker::PhantomData;
 
-/// A virtual memory address in the address space of the program.
-pub type VirtAddr = usize;
-
-/// A physical address as used for virtio.
-pub type PhysAddr = usize;
+pub use crate::memory::{ VirtAddr, PhysAddr, PhysPageNum };
 
 /// A region of contiguous physical memory used for DMA.
 #[derive(Debug)]
 pub struct DMA<H: Hal> {
-    paddr: usize,
+    frame: PhysPageNum,
     pages: usize,
     _phantom: PhantomData<H>,
 }
 
 impl<H: Hal> DMA<H> {
     pub fn new(pages: usize) -> Result<Self> {
-        use crate::memory::Address;
-        let paddr = Address::address(H::dma_alloc(pages));
-        if paddr == 0 {
-            return Err(Error::DmaError);
-        }
+        let frame = H::dma_alloc(pages).ok_or(Error::DmaError)?;
         Ok(DMA {
-            paddr,
+            frame,
             pages,
             _phantom: PhantomData::default(),
         })
     }
 
-    pub fn paddr(&self) -> usize {
-        self.paddr
+    pub fn paddr(&self) -> PhysAddr {
+        self.frame.address()
     }
 
-    pub fn vaddr(&self) -> usize {
-        H::phys_to_virt(self.paddr)
+    pub fn vaddr(&self) -> VirtAddr {
+        H::phys_to_virt(self.paddr())
     }
 
     /// Returns the physical page frame number.
     pub fn pfn(&self) -> u32 {
-        (self.paddr >> 12) as u32
+        self.frame.as_usize() as u32
     }
 
     /// Convert to a buffer
     pub unsafe fn as_buf(&self) -> &'static mut [u8] {
-        core::slice::from_raw_parts_mut(self.vaddr() as _, PAGE_SIZE * self.pages as usize)
+        core::slice::from_raw_parts_mut(self.vaddr().as_ptr(), PAGE_SIZE * self.pages as usize)
     }
 }
 
 impl<H: Hal> Drop for DMA<H> {
     fn drop(&mut self) {
-        let err = H::dma_dealloc(self.paddr as usize, self.pages as usize);
+        let err = H::dma_dealloc(self.frame, self.pages);
         assert_eq!(err, 0, "failed to deallocate DMA");
     }
 }
 
 /// The interface which a particular hardware implementation must implement.
 pub trait Hal {
-    /// Allocates the given number of contiguous physical pages of DMA memory for virtio use.
-    fn dma_alloc(pages: usize) -> PhysAddr;
+    /// Allocates the given number of contiguous physical pages of DMA memory for virtio use,
+    /// returning the first page frame, or `None` if out of memory.
+    fn dma_alloc(pages: usize) -> Option<PhysPageNum>;
     /// Deallocates the given contiguous physical DMA memory pages.
-    fn dma_dealloc(paddr: PhysAddr, pages: usize) -> i32;
+    fn dma_dealloc(frame: PhysPageNum, pages: usize) -> i32;
     /// Converts a physical address used for virtio to a virtual address which the program can
     /// access.
     fn phys_to_virt(paddr: PhysAddr) -> VirtAddr;
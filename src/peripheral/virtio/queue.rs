@@ -54,9 +54,9 @@ impl<H: Hal> VirtQueue<'_, H> {
         header.queue_set(idx as u32, size as u32, PAGE_SIZE as u32, dma.pfn());
 
         let desc =
-            unsafe { slice::from_raw_parts_mut(dma.vaddr() as *mut Descriptor, size as usize) };
-        let avail = unsafe { &mut *((dma.vaddr() + layout.avail_offset) as *mut AvailRing) };
-        let used = unsafe { &mut *((dma.vaddr() + layout.used_offset) as *mut UsedRing) };
+            unsafe { slice::from_raw_parts_mut(dma.vaddr().as_ptr::<Descriptor>(), size as usize) };
+        let avail = unsafe { &mut *(dma.vaddr() + layout.avail_offset).as_ptr::<AvailRing>() };
+        let used = unsafe { &mut *(dma.vaddr() + layout.used_offset).as_ptr::<UsedRing>() };
 
         // Link descriptors together.
         for i in 0..(size - 1) {
@@ -220,7 +220,7 @@ struct Descriptor {
 impl Descriptor {
     fn set_buf<H: Hal>(&mut self, buf: &[u8]) {
         self.addr
-            .write(H::virt_to_phys(buf.as_ptr() as usize) as u64);
+            .write(H::virt_to_phys(VirtAddr::new(buf.as_ptr() as usize)).as_usize() as u64);
         self.len.write(buf.len() as u32);
     }
 }
@@ -19,12 +19,25 @@
 在低地址空间中，在放置完应用 ELF 的所有段之后，会预留 4KiB 的空间作为保护页，得到地址 ustack_base ，这部分实现可以参考创建应用地址空间的 MemorySet::from_elf ， ustack_base 即为其第二个返回值。接下来从 ustack_base 开始按照 TID 从小到大的顺序向高地址放置线程的用户栈，两两之间预留一个保护页放置栈溢出。
 
 在高地址空间中，最高的虚拟页仍然作为跳板页，跳板页中放置的是只读的代码，因此线程之间可以共享。然而，每个线程需要有自己的 Trap 上下文，于是我们在跳板页的下面向低地址按照 TID 从小到大的顺序放置线程的 Trap 上下文。也就是说，只要知道线程的 TID ，我们就可以计算出线程在所属进程地址空间内的用户栈和 Trap 上下文的位置
+
+每个 user stack 下方的保护页面记录在 AddressSpace::stacks 中，访问保护页面时 AddressSpace::fault 返回 Error::StackOverflow；向下增长的 stack 只预先映射栈顶的部分，其余部分在缺页时映射
+
+程序结束处之后为堆，由 AddressSpace::brk 增长或缩小，用户栈位于堆的预留区域之后
+
+mmap 的映射位于 \[4 GiB, 用户地址空间的上界)，按首次适应选取区域，均为按需分配的段；共享映射由 shm::Object 承载
+
+PIE 装载到 config::PIE_BASE 之后，动态链接程序的解释器装载在 mmap 区域中
+
+Layout::random 随机化 stack 与 mmap 区域的起始位置，以及 PIE 程序的装载位置与堆起始位置
 */
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 
 use crate::{
-    runtime::Segment,
-    memory::Flag,
+    runtime::{ Segment, elf::{ self, auxv, Image, Tls } },
+    memory::{ Flag, Address, VirtAddr, VirtPageNum, PageRange, page::{ self, Table, Error, shm, swap } },
 };
 
 /**
@@ -33,31 +46,134 @@ use crate::{
 #[derive(Clone)]
 pub struct AddressSpace {
     /// Address of program entry.
-    pub entry: usize,
+    pub entry: VirtAddr,
     pub segement: Vec<Segment>,
-    /// Page number of program end.
-    pub end: usize,
+    /// 按需分配页框的段，缺页时填充
+    pub lazy: Vec<Lazy>,
+    /// Page number of program end, exclusive.
+    pub end: VirtPageNum,
+    /// 程序断点，堆从 heap() 处开始到 brk 为止
+    pub brk: VirtAddr,
+    /// 是否为位置无关的可执行文件
+    pub pie: bool,
+    pub layout: Layout,
+    /// 线程局部存储的模板
+    pub tls: Option<Tls>,
+    /// 辅助向量的 (类型, 值)，不含结尾的 AT_NULL
+    pub auxv: Vec<(usize, usize)>,
+    /// 由 stack_add 记录的 user stack
+    pub stacks: Vec<Stack>,
+}
+
+/**
+线程的 user stack，range 为预留的全部页，range.start - 1 为保护页面
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stack {
+    pub tid: usize,
+    pub range: PageRange<VirtPageNum>,
+    /// 是否在缺页时向下增长
+    pub grow: bool,
+}
+
+/**
+布局随机化的偏移，单位为页，全为 0 时不随机化
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Layout {
+    /// PIE 相对 config::PIE_BASE 的偏移
+    pub base: usize,
+    /// PIE 的堆相对程序结束处的偏移
+    pub heap: usize,
+    /// 第一个 stack 的偏移
+    pub stack: usize,
+    /// mmap 区域的偏移
+    pub mmap: usize,
+}
+
+impl Layout {
+    /**
+    以 entropy 提供的随机数生成布局
+    */
+    pub fn random(entropy: fn() -> usize) -> Self {
+        Self {
+            base: entropy() % config::BASE_RANDOM,
+            heap: entropy() % config::HEAP_RANDOM,
+            stack: entropy() % config::STACK_RANDOM,
+            mmap: entropy() % config::MMAP_RANDOM,
+        }
+    }
+}
+
+/**
+尚未分配页框的段
+*/
+#[derive(Clone)]
+pub struct Lazy {
+    pub segment: Segment,
+    pub source: Source,
+    /// 共享映射，fork 后父子进程共享页框而不写时复制
+    pub shared: bool,
+}
+
+#[derive(Clone)]
+pub enum Source {
+    /// 全零，用于 bss 与堆
+    Zero,
+    /// ELF 文件，address 为段的起始虚拟地址，range 为段在文件中的范围 \[start, end)
+    File { elf: Arc<[u8]>, address: VirtAddr, range: (usize, usize) },
+    /// 共享内存对象，页号 start 对应对象的第 offset 页
+    Shm { object: Arc<shm::Object>, start: VirtPageNum, offset: usize },
+}
+
+impl Lazy {
+    /**
+    按来源填充页号为 page_number 的页，文件范围之外填零
+    */
+    pub fn fill(&self, page_number: VirtPageNum, page: &mut [u8]) {
+        page.fill(0);
+
+        if let Source::File { elf, address, range } = &self.source {
+            let address = address.as_usize();
+            let page_start = page_number.address().as_usize();
+            let start = page_start.max(address);
+            let end = (page_start + page.len()).min(address + range.1 - range.0);
+            if start < end {
+                let offset = range.0 + start - address;
+                page[start - page_start..end - page_start].copy_from_slice(&elf[offset..offset + end - start]);
+            }
+        }
+    }
 }
 
 impl AddressSpace {
     pub fn empty() -> Self {
         Self {
-            entry: 0,
+            entry: VirtAddr::new(0),
             segement: Vec::new(),
-            end: 0
+            lazy: Vec::new(),
+            end: VirtPageNum::new(0),
+            brk: VirtAddr::new(0),
+            pie: false,
+            layout: Layout::default(),
+            tls: None,
+            auxv: Vec::new(),
+            stacks: Vec::new(),
         }
     }
     /**
     identical map
+
+    不检查重叠与 W^X，新的内核布局应使用 runtime::kernel::Builder
     */
     pub fn new_kernel(
-        entry: usize,
-        mmio: &[(usize, usize)],
-        text: (usize, usize),
-        read_only_data: (usize, usize),
-        data: (usize, usize),
-        static_data: (usize, usize),
-        frame: (usize, usize),
+        entry: VirtAddr,
+        mmio: &[PageRange<VirtPageNum>],
+        text: PageRange<VirtPageNum>,
+        read_only_data: PageRange<VirtPageNum>,
+        data: PageRange<VirtPageNum>,
+        static_data: PageRange<VirtPageNum>,
+        frame: PageRange<VirtPageNum>,
     ) -> Self {
         let mut segement = Vec::new();
 
@@ -86,67 +202,490 @@ impl AddressSpace {
         Self {
             entry,
             segement,
-            end: frame.1
+            lazy: Vec::new(),
+            end: frame.end,
+            brk: frame.end.address(),
+            pie: false,
+            layout: Layout::default(),
+            tls: None,
+            auxv: Vec::new(),
+            stacks: Vec::new(),
+        }
+    }
+    /**
+    解析 elf 数据，得到地址空间的静态信息，PIE 装载到 config::PIE_BASE + layout.base 处
+
+    动态链接的程序返回 elf::Error::Unsupported，应使用 from_elf_lazy
+
+    # 输出
+    (AddressSpace, Image)，segement 与 Image::load 一一对应，段的初始数据为 Image::data 中的对应范围
+    */
+    pub fn from_elf(elf: &[u8], layout: Layout) -> Result<(Self, Image), elf::Error> {
+        let image = Image::parse(elf.into(), VirtPageNum::new(config::PIE_BASE + layout.base).address().as_usize(), true)?;
+        if image.interp.is_some() {
+            return Err(elf::Error::Unsupported("dynamically linked"));
+        }
+
+        let mut space = Self::from_image(&image, layout);
+        space.segement = image.load.iter().map(|(segment, _, _)| *segment).collect();
+
+        Ok((space, image))
+    }
+    /**
+    同 from_elf，但所有段均按需加载，首次访问时从 elf 中读取数据
+
+    动态链接的程序须提供解释器 interp，解释器装载在 mmap 区域中，入口为解释器的入口；没有解释器的程序忽略 interp
+    */
+    pub fn from_elf_lazy(elf: Arc<[u8]>, interp: Option<Arc<[u8]>>, layout: Layout) -> Result<Self, elf::Error> {
+        let image = Image::parse(elf, VirtPageNum::new(config::PIE_BASE + layout.base).address().as_usize(), true)?;
+        let mut space = Self::from_image(&image, layout);
+        space.lazy_load(&image);
+
+        let interp = match (&image.interp, interp) {
+            (None, _) => return Ok(space),
+            (Some(_), None) => return Err(elf::Error::Unsupported("interpreter not provided")),
+            (Some(_), Some(interp)) => interp,
+        };
+
+        let probe = Image::parse(interp.clone(), 0, false)?;
+        let span = probe.span();
+        let start = space.area(span.len()).ok_or(elf::Error::Unsupported("no room for interpreter"))?;
+        let interp = Image::parse(interp, start.address().as_usize().wrapping_sub(span.start.address().as_usize()), false)?;
+        if interp.interp.is_some() {
+            return Err(elf::Error::Unsupported("interpreter with an interpreter"));
+        }
+
+        space.lazy_load(&interp);
+        space.entry = interp.entry;
+        space.auxv = image.auxv(interp.base);
+
+        Ok(space)
+    }
+    /**
+    由解析后的 ELF 得到不含任何段的地址空间
+    */
+    fn from_image(image: &Image, layout: Layout) -> Self {
+        let end = image.span().end;
+        let mut space = Self {
+            entry: image.entry,
+            segement: Vec::new(),
+            lazy: Vec::new(),
+            end,
+            brk: VirtAddr::new(0),
+            pie: image.pie,
+            layout,
+            tls: image.tls,
+            auxv: image.auxv(0),
+            stacks: Vec::new(),
+        };
+        space.brk = space.heap().address();
+
+        space
+    }
+    /**
+    将 image 的所有段记录为从文件按需加载的段
+    */
+    fn lazy_load(&mut self, image: &Image) {
+        for (segment, address, range) in &image.load {
+            self.lazy_push(*segment, Source::File { elf: image.data.clone(), address: *address, range: *range });
         }
     }
     /**
-    解析 elf 数据，得到地址空间的静态信息
+    记录一个按需分配的段，如 bss 或稀疏的堆
+    */
+    pub fn lazy_push(&mut self, segment: Segment, source: Source) {
+        self.lazy.push(Lazy { segment, source, shared: false });
+    }
+    /**
+    缺页处理：为按需分配的段中的页分配页框并填充，向下增长的 stack 映射从该页到已映射部分之间的所有页
+
+    access 为本次访问所需的权限（Flag::R、Flag::W 或 Flag::X）；页为保护页面时返回 Error::StackOverflow，不属于任何按需分配的段时返回 Error::NotMapped
+    */
+    pub fn fault<L: page::Lib>(&self, table: &mut Table, page_number: VirtPageNum, access: Flag) -> Result<(), Error> {
+        use core::slice::from_raw_parts_mut;
+
+        if self.guard(page_number).is_some() {
+            return Err(Error::StackOverflow);
+        }
+        if let Some(stack) = self.stacks.iter().find(|stack| stack.grow && stack.range.contains(page_number)) {
+            let flag = Flag::U | Flag::R | Flag::W;
+            if !flag.contains(access) {
+                return Err(Error::PermissionDenied);
+            }
+
+            let mut page = page_number;
+            while page < stack.range.end && L::try_get(table, page).is_err() && !table.swap.contains_key(&page) {
+                L::try_map(table, page, flag)?;
+                page += 1;
+            }
+
+            return Ok(());
+        }
+
+        let lazy = self.lazy.iter()
+            .find(|lazy| lazy.segment.range.contains(page_number))
+            .ok_or(Error::NotMapped)?;
+        if !lazy.segment.flag.contains(access) {
+            return Err(Error::PermissionDenied);
+        }
+        if let Source::Shm { object, start, offset } = &lazy.source {
+            let frame = object.frame.get(offset + (page_number - *start)).ok_or(Error::Invalid)?;
+            return L::try_map_frame(table, page_number, frame.clone(), lazy.segment.flag);
+        }
+
+        L::try_map(table, page_number, lazy.segment.flag)?;
+        let (frame_number, _, _) = L::try_get(table, page_number)?;
+        let page = unsafe { from_raw_parts_mut(frame_number.address().as_ptr(), Address::address(1)) };
+        lazy.fill(page_number, page);
+
+        Ok(())
+    }
+    /**
+    缺页的完整处理：换出的页由全局交换区换入，写时复制页由 page::Lib::copy_on_write 复制，其余未映射的页由 fault 处理
+
+    页已映射且权限足够时不做任何操作；调用者负责刷新 TLB
+    */
+    pub fn resolve<L: page::Lib>(&self, table: &mut Table, page_number: VirtPageNum, access: Flag) -> Result<(), Error> {
+        if swap::Swap::is_swapped::<L>(table, page_number) {
+            swap::swap_in::<L>(table, page_number)?;
+        }
+
+        let flag = match L::try_get(table, page_number) {
+            Ok((_, flag, _)) => flag,
+            Err(Error::NotMapped) => return self.fault::<L>(table, page_number, access),
+            Err(error) => return Err(error),
+        };
+        if access.contains(Flag::W) && table.cow.contains(&page_number) {
+            return L::copy_on_write(table, page_number);
+        }
+        if !flag.contains(access) {
+            return Err(Error::PermissionDenied);
+        }
+
+        Ok(())
+    }
+    /**
+    同 page::Lib::translated_byte_buffer，但先由 resolve 处理缓冲区中的每一页
+
+    页框不足时先处理的页可能被之后的页换出，此时返回 Error::NotMapped
+    */
+    pub fn translated_byte_buffer<L: page::Lib>(&self, table: &mut Table, address: VirtAddr, len: usize, flag: Flag) -> Result<Vec<&'static mut [u8]>, Error> {
+        let end = address.as_usize().checked_add(len).and_then(VirtAddr::try_new).ok_or(Error::NotMapped)?;
+        for page_number in PageRange::new(address.floor(), end.ceil()) {
+            self.resolve::<L>(table, page_number, flag)?;
+        }
+
+        L::translated_byte_buffer(table, address, len, flag)
+    }
+    /**
+    同 page::Lib::translated_str，但逐页由 resolve 处理
+    */
+    pub fn translated_str<L: page::Lib>(&self, table: &mut Table, address: VirtAddr, max: usize) -> Result<String, Error> {
+        let mut bytes = Vec::new();
+        let mut current = address;
+        loop {
+            self.resolve::<L>(table, current.floor(), Flag::R)?;
+            let next = (current.floor() + 1).address();
+            for byte in L::translated_byte_buffer(table, current, next - current, Flag::R)?.into_iter().flatten() {
+                if *byte == 0 {
+                    return String::from_utf8(bytes).map_err(|_| Error::Encoding);
+                }
+                if current - address == max {
+                    return Err(Error::TooLong);
+                }
+                bytes.push(*byte);
+                current += 1;
+            }
+        }
+    }
+    /**
+    同 page::Lib::translated_ref_mut，但先由 resolve 处理对象所在的页，写时复制页被复制
+    */
+    pub fn translated_ref_mut<L: page::Lib, T>(&self, table: &mut Table, address: VirtAddr) -> Result<&'static mut T, Error> {
+        self.resolve::<L>(table, address.floor(), Flag::R | Flag::W)?;
+
+        L::translated_ref_mut(table, address)
+    }
+    /**
+    建立 len 页的按需分配的映射，返回起始页号
 
     # 输入
-    itext：intervene text 段的页框号
+    - start：为 None 时在 \[config::MMAP_START, config::MMAP_END) 中选取首个足够大的空闲区域；否则映射到该位置，与已有的段重叠的部分先被解除映射
+    - file：文件内容与映射起始处在文件中的偏移，None 为匿名映射；超出文件的部分填零
+    - shared：共享映射在 fork 后仍共享页框，私有映射写时复制
+
+    共享映射由匿名的 shm::Object 承载，页框在映射时分配并填充，之后映射到该对象的各个地址空间共享同一组页框；file 为内存中的文件内容，共享的文件映射不写回文件，需要时由调用者从对象的页框中读取
+    */
+    pub fn mmap<L: page::Lib>(
+        &mut self,
+        table: &mut Table,
+        start: Option<VirtPageNum>,
+        len: usize,
+        flag: Flag,
+        file: Option<(Arc<[u8]>, usize)>,
+        shared: bool,
+    ) -> Result<VirtPageNum, Error> {
+        use core::slice::from_raw_parts_mut;
+
+        if len == 0 {
+            return Err(Error::Invalid);
+        }
+
+        let object = if shared { Some(shm::Object::new(len)?) } else { None };
+        let start = self.place::<L>(table, start, len)?;
+
+        let source = match file {
+            Some((data, offset)) => {
+                let end = (offset + Address::address(len)).min(data.len());
+                Source::File { elf: data, address: start.address(), range: (offset, end.max(offset)) }
+            },
+            None => Source::Zero,
+        };
+        let mut lazy = Lazy {
+            segment: Segment { range: PageRange::new(start, start + len), flag },
+            source,
+            shared,
+        };
+        if let Some(object) = object {
+            for (page_number, frame) in lazy.segment.range.into_iter().zip(object.frame.iter()) {
+                let page = unsafe { from_raw_parts_mut(Address::address(frame.number) as *mut u8, Address::address(1)) };
+                lazy.fill(page_number, page);
+            }
+            lazy.source = Source::Shm { object, start, offset: 0 };
+        }
+        self.lazy.push(lazy);
+
+        Ok(start)
+    }
+    /**
+    将共享内存对象 object 中从第 offset 页开始的 len 页映射到地址空间，start 同 mmap
+
+    页框在首次访问时映射，映射同一对象的各个地址空间可以有不同的权限
+    */
+    pub fn shm_map<L: page::Lib>(
+        &mut self,
+        table: &mut Table,
+        start: Option<VirtPageNum>,
+        object: Arc<shm::Object>,
+        offset: usize,
+        len: usize,
+        flag: Flag,
+    ) -> Result<VirtPageNum, Error> {
+        if len == 0 || offset.checked_add(len).is_none_or(|end| end > object.len()) {
+            return Err(Error::Invalid);
+        }
+
+        let start = self.place::<L>(table, start, len)?;
+        self.lazy.push(Lazy {
+            segment: Segment { range: PageRange::new(start, start + len), flag },
+            source: Source::Shm { object, start, offset },
+            shared: true,
+        });
+
+        Ok(start)
+    }
+    /**
+    映射 len 页的起始页号：start 为 None 时选取空闲区域，否则先解除与之重叠的映射
+
+    指定的区域须位于 \[config::MMAP_START, config::MMAP_END) 中且不与 user stack 及其保护页面重叠，否则返回 Error::Invalid
+    */
+    fn place<L: page::Lib>(&mut self, table: &mut Table, start: Option<VirtPageNum>, len: usize) -> Result<VirtPageNum, Error> {
+        match start {
+            Some(start) => {
+                let end = start.as_usize().checked_add(len)
+                    .filter(|&end| start.as_usize() >= config::MMAP_START && end <= config::MMAP_END)
+                    .and_then(VirtPageNum::try_new)
+                    .ok_or(Error::Invalid)?;
+                let range = PageRange::new(start, end);
+                if self.reserved().any(|reserved| reserved.start < range.end && range.start < reserved.end) {
+                    return Err(Error::Invalid);
+                }
+
+                self.munmap::<L>(table, range)?;
+                Ok(start)
+            },
+            None => self.area(len).ok_or(Error::OutOfMemory),
+        }
+    }
+    /**
+    解除页号范围内的映射，部分重叠的段被拆分；范围内没有映射时不做任何操作
+    */
+    pub fn munmap<L: page::Lib>(&mut self, table: &mut Table, range: PageRange<VirtPageNum>) -> Result<(), Error> {
+        let removed = carve(&mut self.segement, range, |segment| segment).into_iter()
+            .chain(carve(&mut self.lazy, range, |lazy| &mut lazy.segment).into_iter().map(|lazy| lazy.segment));
+
+        for segment in removed {
+            for page in segment.range {
+                match L::try_unmap(table, page) {
+                    Ok(()) | Err(Error::NotMapped) => {},
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+
+        Ok(())
+    }
+    /**
+    修改页号范围内的权限，部分重叠的段被拆分，已映射的页表项随之更新；调用者负责刷新 TLB
+
+    范围内存在未映射的页时返回 Error::NotMapped 且不做任何修改
+    */
+    pub fn mprotect<L: page::Lib>(&mut self, table: &mut Table, range: PageRange<VirtPageNum>, flag: Flag) -> Result<(), Error> {
+        let covered = range.iter().all(|page| {
+            self.segement.iter().chain(self.lazy.iter().map(|lazy| &lazy.segment))
+                .any(|segment| segment.range.contains(page))
+        });
+        if !covered {
+            return Err(Error::NotMapped);
+        }
+
+        let mut segement = carve(&mut self.segement, range, |segment| segment);
+        let mut lazy = carve(&mut self.lazy, range, |lazy| &mut lazy.segment);
+        segement.iter_mut().for_each(|segment| segment.flag = flag);
+        lazy.iter_mut().for_each(|lazy| lazy.segment.flag = flag);
+        self.segement.append(&mut segement);
+        self.lazy.append(&mut lazy);
+
+        let permission = Flag::R | Flag::W | Flag::X | Flag::U;
+        for page in range {
+            if L::try_get(table, page).is_err() && !table.swap.contains_key(&page) {
+                continue;
+            }
+
+            // a write to a frame shared with another table must copy it first
+            let private = !table.shared.contains(&page)
+                && table.frame.get(&page).is_some_and(|frame| Arc::strong_count(frame) > 1);
+            let mut current = flag & permission;
+            if private && current.contains(Flag::W) {
+                table.cow.insert(page);
+                current -= Flag::W;
+            } else {
+                table.cow.remove(&page);
+            }
+
+            let entry = L::try_leaf(table, page)?;
+            let old = L::flag(entry);
+            L::set_flag(entry, (old - permission) | current);
+        }
+
+        Ok(())
+    }
+    /**
+    堆的起始页号，只有 PIE 的堆起始位置被随机化
+    */
+    #[inline]
+    pub fn heap(&self) -> VirtPageNum {
+        if self.pie { self.end + self.layout.heap } else { self.end }
+    }
+    /**
+    设置程序断点，增长时映射填零的页，缩小时解除映射，返回新的断点
+
+    断点不能低于程序结束处，也不能进入第一个 stack 之前的保护页面；失败时断点不变
+    */
+    pub fn brk<L: page::Lib>(&mut self, table: &mut Table, brk: VirtAddr) -> Result<VirtAddr, Error> {
+        let limit = self.stack(0, 0).range.start - 1;
+        if brk < self.heap().address() || brk > limit.address() {
+            return Err(Error::OutOfMemory);
+        }
+
+        let (old, new) = (self.brk.ceil(), brk.ceil());
+        if new > old {
+            L::try_map_area(table, PageRange::new(old, new), Flag::U | Flag::R | Flag::W)?;
+        } else {
+            L::unmap_area(table, PageRange::new(new, old));
+        }
+        self.brk = brk;
+
+        Ok(brk)
+    }
+    /**
+    将程序断点移动 increment 字节，返回原来的断点
+    */
+    pub fn sbrk<L: page::Lib>(&mut self, table: &mut Table, increment: isize) -> Result<VirtAddr, Error> {
+        let old = self.brk;
+        let brk = old.as_usize().checked_add_signed(increment).ok_or(Error::OutOfMemory)?;
+        self.brk::<L>(table, VirtAddr::try_new(brk).ok_or(Error::OutOfMemory)?)?;
+
+        Ok(old)
+    }
+    /**
+    System V 初始栈，top 为栈顶（不含）
+
+    自高地址向低地址依次为 argv 与 envp 的字符串、AT_RANDOM 指向的 16 字节，以及按 16 字节对齐的 auxv、envp、argv 与 argc
 
     # 输出
-    (AddressSpace, data_offset)
+    (sp, 内容)，内容从 sp 所在页的起始处开始到 top 为止，sp 之前填零
     */
-    pub fn from_elf(elf: &[u8]) -> (Self, Vec<(usize, usize)>) {
-        // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf).unwrap();
-        let magic = elf.header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+    pub fn initial_stack(&self, top: VirtAddr, argv: &[&[u8]], envp: &[&[u8]], random: [u8; 16]) -> (VirtAddr, Vec<u8>) {
+        let size = argv.iter().chain(envp).map(|string| string.len() + 1).sum::<usize>() + random.len();
+        let start = top.as_usize() - size;
+
+        let mut strings = Vec::with_capacity(size);
+        let mut pointer = Vec::new();
+        for string in argv.iter().chain(envp) {
+            pointer.push(start + strings.len());
+            strings.extend_from_slice(string);
+            strings.push(0);
+        }
+        let random_address = start + strings.len();
+        strings.extend_from_slice(&random);
 
-        let entry = elf.header.pt2.entry_point() as usize;
-        let mut space_end = 0;
+        let mut vector = Vec::from([argv.len()]);
+        vector.extend_from_slice(&pointer[..argv.len()]);
+        vector.push(0);
+        vector.extend_from_slice(&pointer[argv.len()..]);
+        vector.push(0);
+        for (kind, value) in self.auxv.iter().chain(&[(auxv::RANDOM, random_address), (auxv::NULL, 0)]) {
+            vector.extend_from_slice(&[*kind, *value]);
+        }
 
-        let ph_count = elf.header.pt2.ph_count();
-        let mut data_offset = Vec::new();
+        let sp = VirtAddr::new((start - vector.len() * size_of::<usize>()) & !0xf);
+        let page = sp.floor().address();
+        let mut data = vec![0u8; top - page];
+        for (i, word) in vector.iter().enumerate() {
+            let at = sp - page + i * size_of::<usize>();
+            data[at..at + size_of::<usize>()].copy_from_slice(&word.to_ne_bytes());
+        }
+        data[start - page.as_usize()..].copy_from_slice(&strings);
 
-        let mut segement = Vec::new();
-        for i in 0..ph_count {
-            let program_header = elf.program_header(i).unwrap();
-            if program_header.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start = program_header.virtual_addr() as usize;
-                let size = program_header.mem_size() as usize;
-                let end = start + size - 1;
-   
-                use crate::memory::Address;
-                let range = (Address::number(start), Address::number(end));
-
-                let ph_flags = program_header.flags();
-                let mut flag = Flag::U;
-                if ph_flags.is_read() { flag |= Flag::R; }
-                if ph_flags.is_write() { flag |= Flag::W; }
-                if ph_flags.is_execute() { flag |= Flag::X; }
-
-                segement.push(Segment { range, flag });
-                data_offset.push(
-                    (program_header.offset() as usize, (program_header.offset() + program_header.file_size()) as usize)
-                );
-
-                space_end = range.1;
+        (sp, data)
+    }
+    /**
+    首个能容纳 len 页且不与任何段重叠的区域
+    */
+    fn area(&self, len: usize) -> Option<VirtPageNum> {
+        let mut used: Vec<PageRange<VirtPageNum>> = self.segement.iter()
+            .chain(self.lazy.iter().map(|lazy| &lazy.segment))
+            .map(|segment| segment.range)
+            .chain(self.reserved())
+            .collect();
+        used.sort_by_key(|range| range.start);
+
+        let mut start = VirtPageNum::new(config::MMAP_START + self.layout.mmap);
+        for range in used {
+            if range.end <= start {
+                continue;
+            }
+            if range.start.as_usize() >= start.as_usize() + len {
+                break;
             }
+            start = range.end;
         }
- 
-        (
-            Self { entry, segement, end: space_end },
-            data_offset,
-        )
+
+        (start.as_usize() + len <= config::MMAP_END).then_some(start)
+    }
+    /**
+    user stack 预留的页与其下方的保护页面
+    */
+    fn reserved(&self) -> impl Iterator<Item = PageRange<VirtPageNum>> + '_ {
+        self.stacks.iter().map(|stack| PageRange::new(stack.range.start - 1, stack.range.end))
     }
 
     pub fn idata(tid: usize) -> Segment {
-        let page_number = config::INTERVENE_TEXT - 1 - tid * 2;
+        let page_number = VirtPageNum::new(config::INTERVENE_TEXT - 1 - tid * 2);
 
         Segment {
-            range: (page_number, page_number),
+            range: PageRange::new(page_number, page_number + 1),
             flag: Flag::R | Flag::W
         }
     }
@@ -156,30 +695,59 @@ impl AddressSpace {
     #[inline]
     pub fn itext() -> Segment {
         Segment {
-            range: (config::INTERVENE_TEXT, config::INTERVENE_TEXT),
+            range: PageRange::new(VirtPageNum::new(config::INTERVENE_TEXT), VirtPageNum::new(config::INTERVENE_TEXT + 1)),
             flag: Flag::R | Flag::X
         }
     }
     /**
+    记录线程 tid 的 user stack 及其下方的保护页面，返回需要映射的段
+
+    size 为 stack 的页数上限（rlimit）；grow 为 Some(n) 时只返回栈顶的 n 页，其余部分在缺页时由 fault 向下增长
+    */
+    pub fn stack_add(&mut self, tid: usize, size: usize, grow: Option<usize>) -> Segment {
+        let range = self.stack(tid, size).range;
+        self.stacks.retain(|stack| stack.tid != tid);
+        self.stacks.push(Stack { tid, range, grow: grow.is_some() });
+
+        let initial = grow.map_or(size, |initial| initial.min(size));
+        Segment {
+            range: PageRange::new(range.end - initial, range.end),
+            flag: Flag::U | Flag::R | Flag::W
+        }
+    }
+    /**
+    移除线程 tid 的 user stack 的记录，不解除映射
+    */
+    pub fn stack_remove(&mut self, tid: usize) {
+        self.stacks.retain(|stack| stack.tid != tid);
+    }
+    /**
+    page_number 为保护页面时返回其上方的 stack
+    */
+    pub fn guard(&self, page_number: VirtPageNum) -> Option<&Stack> {
+        self.stacks.iter().find(|stack| stack.range.start - 1 == page_number)
+    }
+    /**
     线程的栈
 
     # 输入
     thread id
 
-    两个相邻的 stack 之间有一个保护页面
+    程序结束处之后预留 config::HEAP 页作为堆，第一个 stack 与堆、两个相邻的 stack 之间各有一个保护页面
     */
     pub fn stack(&self, tid: usize, size: usize) -> Segment {
-        let start = self.end + 1 + tid * (size + 1);
-        let end = start + size - 1;
+        let start = self.heap() + config::HEAP + 1 + self.layout.stack + tid * (size + 1);
 
         Segment {
-            range: (start, end),
+            range: PageRange::new(start, start + size),
             flag: Flag::R | Flag::W
         }
     }
 }
 
 mod config {
+    use crate::memory::address::config::VIRT_WIDTH;
+
     // 4 GB = 4 * 2^30 B
     // 4 KB = 4 * 2^10 B
 
@@ -189,4 +757,470 @@ mod config {
     单位：页（page）
     */
     pub const INTERVENE_TEXT: usize = (1 << 52) - 1;
+    /// 堆的最大页数，1 GiB
+    pub const HEAP: usize = 0x4_0000;
+    /// mmap 区域的起始页号，4 GiB
+    pub const MMAP_START: usize = 0x10_0000;
+    /// mmap 区域的结束页号，用户地址空间即虚拟地址空间低半部分的最后一页，Sv39 时约为 256 GiB；该页本身不被映射，结束页号总是合法的页号
+    pub const MMAP_END: usize = (1 << (VIRT_WIDTH - 1 - 12)) - 1;
+    /// PIE 的装载位置，16 MiB
+    pub const PIE_BASE: usize = 0x1000;
+    /// PIE 装载位置随机偏移的上界，256 MiB
+    pub const BASE_RANDOM: usize = 0x1_0000;
+    /// 堆起始位置随机偏移的上界，32 MiB
+    pub const HEAP_RANDOM: usize = 0x2000;
+    /// stack 起始位置随机偏移的上界，64 MiB
+    pub const STACK_RANDOM: usize = 0x4000;
+    /// mmap 区域起始位置随机偏移的上界，64 GiB
+    pub const MMAP_RANDOM: usize = 0x100_0000;
+}
+
+/**
+将 list 中与 range 相交的部分拆出并返回，不相交的部分（包括被拆分的段的两端）留在 list 中
+*/
+fn carve<T: Clone>(list: &mut Vec<T>, range: PageRange<VirtPageNum>, segment: fn(&mut T) -> &mut Segment) -> Vec<T> {
+    let mut inside = Vec::new();
+    let mut outside = Vec::new();
+
+    for mut item in list.drain(..) {
+        let current = segment(&mut item).range;
+        let start = current.start.max(range.start);
+        let end = current.end.min(range.end);
+        if start >= end {
+            outside.push(item);
+            continue;
+        }
+
+        if current.start < start {
+            let mut left = item.clone();
+            segment(&mut left).range = PageRange::new(current.start, start);
+            outside.push(left);
+        }
+        if end < current.end {
+            let mut right = item.clone();
+            segment(&mut right).range = PageRange::new(end, current.end);
+            outside.push(right);
+        }
+        segment(&mut item).range = PageRange::new(start, end);
+        inside.push(item);
+    }
+
+    *list = outside;
+    inside
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::{
+        memory::{ Flag, VirtAddr, VirtPageNum, PhysPageNum, PageRange, page::{ Lib, Table, Error, format::Sv39, sim::{ self, Mmu, Fault } } },
+        intervene::Cause,
+        runtime::Segment,
+    };
+    use super::{ AddressSpace, Layout, Lazy, Source };
+
+    mod config {
+        /// 测试使用的页框数
+        pub const FRAME: usize = 32;
+    }
+
+    fn init() {
+        use lazy_static::lazy_static;
+
+        lazy_static! {
+            static ref POOL: PageRange<PhysPageNum> = sim::init(config::FRAME);
+        }
+
+        lazy_static::initialize(&POOL);
+    }
+
+    #[test]
+    fn fill() {
+        let elf: Arc<[u8]> = (0..=255u8).cycle().take(0x3000).collect::<Vec<_>>().into();
+        // data starts at 0x1100 in memory, 0x1800 bytes from file offset 0x200, followed by bss
+        let lazy = Lazy {
+            segment: Segment { range: PageRange::inclusive(VirtPageNum::new(1), VirtPageNum::new(4)), flag: Flag::U | Flag::R | Flag::W },
+            source: Source::File { elf: elf.clone(), address: VirtAddr::new(0x1100), range: (0x200, 0x1a00) },
+            shared: false,
+        };
+
+        let mut page = vec![0xffu8; 0x1000];
+        lazy.fill(VirtPageNum::new(1), &mut page);
+        assert!(page[..0x100].iter().all(|&byte| byte == 0));
+        assert_eq!(&page[0x100..], &elf[0x200..0x1100]);
+
+        lazy.fill(VirtPageNum::new(2), &mut page);
+        assert_eq!(&page[..0x900], &elf[0x1100..0x1a00]);
+        assert!(page[0x900..].iter().all(|&byte| byte == 0));
+
+        page.fill(0xff);
+        lazy.fill(VirtPageNum::new(4), &mut page);
+        assert!(page.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn mmap() {
+        init();
+
+        let mut space = AddressSpace::empty();
+        let mut table = Table::new();
+        let file: Arc<[u8]> = vec![7u8; 0x1800].into();
+        let flag = Flag::U | Flag::R | Flag::W;
+
+        let anonymous = space.mmap::<Sv39>(&mut table, None, 4, flag, None, false).unwrap();
+        assert_eq!(anonymous, VirtPageNum::new(0x10_0000));
+        let mapped = space.mmap::<Sv39>(&mut table, None, 2, Flag::U | Flag::R, Some((file, 0x800)), false).unwrap();
+        assert_eq!(mapped, anonymous + 4);
+        assert_eq!(space.mmap::<Sv39>(&mut table, None, 0, flag, None, false), Err(Error::Invalid));
+
+        // the file ends in the first page, the rest is zero
+        for page in [mapped, mapped + 1, anonymous + 1] {
+            space.fault::<Sv39>(&mut table, page, Flag::R).unwrap();
+        }
+        let mut buffer = [0xffu8; 2];
+        Mmu::<Sv39>::new(true).load(&table, mapped.address() + 0xfff, &mut buffer).unwrap();
+        assert_eq!(buffer, [7, 0]);
+
+        // unmapping the middle splits the anonymous mapping, the hole is reused
+        space.munmap::<Sv39>(&mut table, PageRange::new(anonymous + 1, anonymous + 3)).unwrap();
+        assert_eq!(Sv39::try_get(&table, anonymous + 1), Err(Error::NotMapped));
+        assert_eq!(space.fault::<Sv39>(&mut table, anonymous + 2, Flag::R), Err(Error::NotMapped));
+        assert!(space.fault::<Sv39>(&mut table, anonymous + 3, Flag::W).is_ok());
+        assert_eq!(space.mmap::<Sv39>(&mut table, None, 2, flag, None, false), Ok(anonymous + 1));
+
+        // a fixed mapping replaces what it overlaps
+        assert_eq!(space.mmap::<Sv39>(&mut table, Some(mapped), 1, flag, None, false), Ok(mapped));
+        assert_eq!(Sv39::try_get(&table, mapped), Err(Error::NotMapped));
+        let mut range: Vec<_> = space.lazy.iter().map(|lazy| lazy.segment.range).collect();
+        range.sort_by_key(|range| range.start);
+        assert_eq!(range, [
+            PageRange::new(anonymous, anonymous + 1),
+            PageRange::new(anonymous + 1, anonymous + 3),
+            PageRange::new(anonymous + 3, anonymous + 4),
+            PageRange::new(mapped, mapped + 1),
+            PageRange::new(mapped + 1, mapped + 2),
+        ]);
+
+        // a shared mapping faulted in only after fork still shares its frame
+        let shared = space.mmap::<Sv39>(&mut table, None, 1, flag, None, true).unwrap();
+        let mut child = Sv39::fork(&mut table).unwrap();
+        space.clone().fault::<Sv39>(&mut child, shared, Flag::W).unwrap();
+        space.fault::<Sv39>(&mut table, shared, Flag::R).unwrap();
+        assert_eq!(Sv39::try_get(&table, shared).unwrap().0, Sv39::try_get(&child, shared).unwrap().0);
+
+        child.destroy();
+        table.destroy();
+    }
+
+    #[test]
+    fn place() {
+        use super::config::{ MMAP_START, MMAP_END };
+
+        init();
+
+        let mut space = AddressSpace::empty();
+        let mut table = Table::new();
+        let flag = Flag::U | Flag::R | Flag::W;
+
+        // fixed mappings outside the mmap window or past the address width
+        assert_eq!(space.mmap::<Sv39>(&mut table, Some(VirtPageNum::new(0x10)), 1, flag, None, false), Err(Error::Invalid));
+        assert_eq!(space.mmap::<Sv39>(&mut table, Some(VirtPageNum::new(MMAP_END - 1)), 2, flag, None, false), Err(Error::Invalid));
+        assert_eq!(space.mmap::<Sv39>(&mut table, Some(VirtPageNum::new(MMAP_START)), usize::MAX, flag, None, false), Err(Error::Invalid));
+        assert_eq!(space.mmap::<Sv39>(&mut table, Some(VirtPageNum::new(MMAP_END - 1)), 1, flag, None, false), Ok(VirtPageNum::new(MMAP_END - 1)));
+
+        // a stack slot in the window, with its guard page at MMAP_START
+        space.layout.stack = MMAP_START + 1 - space.stack(0, 0).range.start.as_usize();
+        let stack = space.stack_add(0, 4, Some(1));
+        assert_eq!(stack.range.end, VirtPageNum::new(MMAP_START + 5));
+        assert_eq!(space.mmap::<Sv39>(&mut table, None, 2, flag, None, false), Ok(stack.range.end));
+        assert_eq!(space.mmap::<Sv39>(&mut table, Some(VirtPageNum::new(MMAP_START)), 1, flag, None, false), Err(Error::Invalid));
+        assert_eq!(space.mmap::<Sv39>(&mut table, Some(VirtPageNum::new(MMAP_START + 2)), 1, flag, None, false), Err(Error::Invalid));
+
+        table.destroy();
+    }
+
+    #[test]
+    fn mprotect() {
+        init();
+
+        let mut space = AddressSpace::empty();
+        let mut table = Table::new();
+        let flag = Flag::U | Flag::R | Flag::W;
+        let start = space.mmap::<Sv39>(&mut table, None, 3, flag, None, false).unwrap();
+        let shared = space.mmap::<Sv39>(&mut table, None, 1, flag, None, true).unwrap();
+        for page in PageRange::new(start, shared + 1) {
+            space.fault::<Sv39>(&mut table, page, Flag::W).unwrap();
+        }
+
+        let mmu = Mmu::<Sv39>::new(true);
+        space.mprotect::<Sv39>(&mut table, PageRange::new(start + 1, start + 2), Flag::U | Flag::R).unwrap();
+        assert_eq!(Sv39::get(&mut table, start + 1).1, Flag::V | Flag::U | Flag::R);
+        assert_eq!(mmu.store(&table, (start + 1).address(), b"x"), Err(Fault::Page(Cause::PageStoreFault)));
+        assert_eq!(space.fault::<Sv39>(&mut table, start + 1, Flag::W), Err(Error::PermissionDenied));
+        assert_eq!(space.lazy.len(), 4);
+        assert_eq!(space.mprotect::<Sv39>(&mut table, PageRange::new(start + 2, start + 5), Flag::R), Err(Error::NotMapped));
+
+        // the shared page is not copied on write
+        let child = Sv39::fork(&mut table).unwrap();
+        assert!(child.cow.contains(&start) && !child.cow.contains(&shared));
+        mmu.store(&child, shared.address(), b"s").unwrap();
+        let mut buffer = [0u8];
+        mmu.load(&table, shared.address(), &mut buffer).unwrap();
+        assert_eq!(&buffer, b"s");
+
+        // a frame shared with the child becomes copy-on-write instead of writable
+        space.mprotect::<Sv39>(&mut table, PageRange::new(start, shared + 1), flag).unwrap();
+        assert!(table.cow.contains(&(start + 1)) && !table.cow.contains(&shared));
+        assert!(!Sv39::get(&mut table, start + 1).1.contains(Flag::W));
+        assert!(Sv39::get(&mut table, shared).1.contains(Flag::W));
+
+        child.destroy();
+        table.destroy();
+    }
+
+    #[test]
+    fn resolve() {
+        init();
+
+        let mut space = AddressSpace::empty();
+        let mut table = Table::new();
+        let start = space.mmap::<Sv39>(&mut table, None, 1, Flag::U | Flag::R | Flag::W, None, false).unwrap();
+        space.resolve::<Sv39>(&mut table, start, Flag::R).unwrap();
+        space.resolve::<Sv39>(&mut table, start, Flag::R).unwrap();
+        assert_eq!(space.resolve::<Sv39>(&mut table, start, Flag::X), Err(Error::PermissionDenied));
+        assert_eq!(space.resolve::<Sv39>(&mut table, start + 1, Flag::R), Err(Error::NotMapped));
+
+        // a write fault copies the page shared with the child
+        let child = Sv39::fork(&mut table).unwrap();
+        space.resolve::<Sv39>(&mut table, start, Flag::W).unwrap();
+        assert!(!table.cow.contains(&start));
+        assert!(Sv39::get(&mut table, start).1.contains(Flag::W));
+        assert_ne!(Sv39::get(&mut table, start).0, Sv39::try_get(&child, start).unwrap().0);
+
+        // translation faults lazy pages in and resolves copy-on-write pages
+        let mut child = child;
+        *space.translated_ref_mut::<Sv39, u64>(&mut child, start.address()).unwrap() = 7;
+        assert!(!child.cow.contains(&start));
+        assert_eq!(*space.translated_ref_mut::<Sv39, u64>(&mut table, start.address()).unwrap(), 0);
+
+        let lazy = space.mmap::<Sv39>(&mut table, None, 2, Flag::U | Flag::R | Flag::W, None, false).unwrap();
+        let buffer = space.translated_byte_buffer::<Sv39>(&mut table, lazy.address() + 0xffe, 4, Flag::W).unwrap();
+        for (slice, data) in buffer.into_iter().zip([&b"ab"[..], &b"c\0"[..]]) {
+            slice.copy_from_slice(data);
+        }
+        assert_eq!(space.translated_str::<Sv39>(&mut table, lazy.address() + 0xffe, 3).unwrap(), "abc");
+        assert_eq!(space.translated_str::<Sv39>(&mut table, lazy.address() + 0xffe, 2), Err(Error::TooLong));
+
+        // a multi-byte character split by the page boundary
+        let buffer = space.translated_byte_buffer::<Sv39>(&mut table, lazy.address() + 0xfff, 3, Flag::W).unwrap();
+        for (slice, data) in buffer.into_iter().zip([&"é".as_bytes()[..1], &"é\0".as_bytes()[1..]]) {
+            slice.copy_from_slice(data);
+        }
+        assert_eq!(space.translated_str::<Sv39>(&mut table, lazy.address() + 0xfff, 16).unwrap(), "é");
+
+        child.destroy();
+        table.destroy();
+    }
+
+    #[test]
+    fn brk() {
+        init();
+
+        let mut space = AddressSpace::empty();
+        space.end = VirtPageNum::new(0x10);
+        space.brk = space.end.address();
+        let mut table = Table::new();
+        let heap = space.end.address();
+
+        assert_eq!(space.sbrk::<Sv39>(&mut table, 0x1800), Ok(heap));
+        assert_eq!(space.brk, heap + 0x1800);
+        assert_eq!(table.frame.len(), 2);
+        let mmu = Mmu::<Sv39>::new(true);
+        mmu.store(&table, heap + 0x17ff, b"x").unwrap();
+
+        assert_eq!(space.brk::<Sv39>(&mut table, heap + 0x800), Ok(heap + 0x800));
+        assert_eq!(table.frame.len(), 1);
+        assert_eq!(mmu.store(&table, heap + 0x1000, b"x"), Err(Fault::Page(Cause::PageStoreFault)));
+
+        // the heap ends at the guard page of the first stack
+        let limit = space.stack(0, 4).range.start - 1;
+        assert_eq!(space.brk::<Sv39>(&mut table, limit.address() + 1), Err(Error::OutOfMemory));
+        assert_eq!(space.brk::<Sv39>(&mut table, heap - 1), Err(Error::OutOfMemory));
+        assert_eq!(space.sbrk::<Sv39>(&mut table, -0x800), Ok(heap + 0x800));
+        assert!(table.frame.is_empty());
+        // a non-canonical break is rejected instead of panicking
+        assert_eq!(space.sbrk::<Sv39>(&mut table, 1 << 62), Err(Error::OutOfMemory));
+        assert_eq!(space.brk, heap);
+
+        table.destroy();
+    }
+
+    #[test]
+    fn randomize() {
+        init();
+
+        fn entropy() -> usize {
+            0x1234_5678_9abc
+        }
+
+        let layout = Layout::random(entropy);
+        assert_eq!(layout, Layout { base: 0x9abc, heap: 0x1abc, stack: 0x1abc, mmap: 0x789abc });
+
+        let mut space = AddressSpace::empty();
+        space.end = VirtPageNum::new(0x10);
+        let stack = space.stack(1, 4);
+        let mut table = Table::new();
+
+        space.layout = layout;
+        assert_eq!(space.heap(), space.end);
+        assert_eq!(space.stack(1, 4).range.start, stack.range.start + 0x1abc);
+        assert_eq!(space.mmap::<Sv39>(&mut table, None, 1, Flag::R, None, false), Ok(VirtPageNum::new(0x10_0000 + 0x789abc)));
+
+        // the heap of a PIE program moves as well
+        space.pie = true;
+        assert_eq!(space.heap(), VirtPageNum::new(0x10 + 0x1abc));
+
+        table.destroy();
+    }
+
+    #[test]
+    fn dynamic() {
+        use crate::runtime::elf::{ self, auxv, test::{ build, PT_LOAD, PT_INTERP } };
+
+        let text = [0u8; 0x10];
+        let program = build(3, 0x10, &[(PT_INTERP, 4, 0, b"/lib/ld.so\0", 11), (PT_LOAD, 5, 0, &text, 0x2000)]);
+        let interp = build(3, 0x20, &[(PT_LOAD, 5, 0, &text, 0x3000)]);
+        let layout = Layout { base: 0x10, ..Layout::default() };
+
+        // the program is loaded after config::PIE_BASE, the interpreter in the mmap area
+        let space = AddressSpace::from_elf_lazy(program.clone().into(), Some(interp.into()), layout).unwrap();
+        let base = VirtPageNum::new(0x1010).address();
+        let interp_base = VirtPageNum::new(0x10_0000).address();
+        assert_eq!(space.entry, interp_base + 0x20);
+        assert_eq!(space.end, VirtPageNum::new(0x1012));
+        assert_eq!(space.lazy[1].segment.range, PageRange::new(VirtPageNum::new(0x10_0000), VirtPageNum::new(0x10_0003)));
+        assert!(space.auxv.contains(&(auxv::ENTRY, (base + 0x10).as_usize())));
+        assert!(space.auxv.contains(&(auxv::BASE, interp_base.as_usize())));
+
+        assert_eq!(
+            AddressSpace::from_elf_lazy(program.clone().into(), None, layout).err(),
+            Some(elf::Error::Unsupported("interpreter not provided"))
+        );
+        assert_eq!(AddressSpace::from_elf(&program, layout).err(), Some(elf::Error::Unsupported("dynamically linked")));
+    }
+
+    #[test]
+    fn initial_stack() {
+        use crate::runtime::elf::auxv;
+
+        let mut space = AddressSpace::empty();
+        space.auxv = vec![(auxv::PAGESZ, 0x1000), (auxv::ENTRY, 0x1_0000)];
+        let top = VirtAddr::new(0x8000);
+        let (sp, data) = space.initial_stack(top, &[b"sh", b"-c"], &[b"HOME=/"], [9; 16]);
+
+        assert_eq!(sp.as_usize() % 16, 0);
+        assert_eq!(data.len(), top - sp.floor().address());
+        let word = |address: usize| {
+            let at = address - sp.floor().address().as_usize();
+            usize::from_ne_bytes(data[at..at + 8].try_into().unwrap())
+        };
+        let string = |address: usize| {
+            let at = address - sp.floor().address().as_usize();
+            data[at..].split(|&byte| byte == 0).next().unwrap()
+        };
+
+        // argc, argv, NULL, envp, NULL, auxv
+        let stack: Vec<_> = (0..13).map(|i| word(sp.as_usize() + i * 8)).collect();
+        assert_eq!(stack[0], 2);
+        assert_eq!((string(stack[1]), string(stack[2]), stack[3]), (&b"sh"[..], &b"-c"[..], 0));
+        assert_eq!((string(stack[4]), stack[5]), (&b"HOME=/"[..], 0));
+        assert_eq!(stack[6..10], [auxv::PAGESZ, 0x1000, auxv::ENTRY, 0x1_0000]);
+        assert_eq!((stack[10], stack[12]), (auxv::RANDOM, auxv::NULL));
+        assert_eq!(stack[11], top.as_usize() - 16);
+        assert!(data[data.len() - 16..].iter().all(|&byte| byte == 9));
+    }
+
+    #[test]
+    fn stack() {
+        init();
+
+        let mut space = AddressSpace::empty();
+        let mut table = Table::new();
+        let fixed = space.stack_add(0, 2, None);
+        let grow = space.stack_add(1, 4, Some(1));
+        let reserved = space.stack(1, 4).range;
+        assert_eq!(fixed.range, space.stack(0, 2).range);
+        assert_eq!(grow.range, PageRange::new(reserved.end - 1, reserved.end));
+        Sv39::try_map_area(&mut table, fixed.range, fixed.flag).unwrap();
+        Sv39::try_map_area(&mut table, grow.range, grow.flag).unwrap();
+
+        // the guard pages below both stacks overflow
+        assert_eq!(space.guard(fixed.range.start - 1).map(|stack| stack.tid), Some(0));
+        assert_eq!(space.fault::<Sv39>(&mut table, fixed.range.start - 1, Flag::W), Err(Error::StackOverflow));
+        assert_eq!(space.fault::<Sv39>(&mut table, reserved.start - 1, Flag::R), Err(Error::StackOverflow));
+
+        // the growing stack maps everything up to the mapped part
+        assert_eq!(space.fault::<Sv39>(&mut table, reserved.start + 1, Flag::X), Err(Error::PermissionDenied));
+        space.fault::<Sv39>(&mut table, reserved.start + 1, Flag::W).unwrap();
+        assert_eq!(table.frame.len(), 5);
+        assert!(Sv39::try_get(&table, reserved.start).is_err());
+        Mmu::<Sv39>::new(true).store(&table, (reserved.start + 2).address(), b"x").unwrap();
+
+        space.stack_remove(1);
+        assert_eq!(space.fault::<Sv39>(&mut table, reserved.start, Flag::W), Err(Error::NotMapped));
+
+        table.destroy();
+    }
+
+    #[test]
+    fn shm() {
+        use alloc::sync::Arc;
+        use crate::memory::page::shm;
+
+        init();
+
+        let object = shm::create("test-shm", 3).unwrap();
+        assert!(Arc::ptr_eq(&shm::open("test-shm").unwrap(), &object));
+        let frame = Arc::downgrade(&object.frame[2]);
+
+        let (mut writer, mut reader) = (AddressSpace::empty(), AddressSpace::empty());
+        let (mut table, mut other) = (Table::new(), Table::new());
+        let start = writer.shm_map::<Sv39>(&mut table, None, object.clone(), 1, 2, Flag::U | Flag::R | Flag::W).unwrap();
+        let view = reader.shm_map::<Sv39>(&mut other, Some(VirtPageNum::new(0x20_0000)), object.clone(), 2, 1, Flag::U | Flag::R).unwrap();
+        assert_eq!(reader.shm_map::<Sv39>(&mut other, None, object.clone(), 2, 2, Flag::R), Err(Error::Invalid));
+
+        // the second page of the writer is the only page of the reader
+        for page in [start, start + 1] {
+            writer.fault::<Sv39>(&mut table, page, Flag::W).unwrap();
+        }
+        reader.fault::<Sv39>(&mut other, view, Flag::R).unwrap();
+        assert_eq!(reader.fault::<Sv39>(&mut other, view, Flag::W), Err(Error::PermissionDenied));
+
+        let mmu = Mmu::<Sv39>::new(true);
+        mmu.store(&table, (start + 1).address() + 8, b"shared").unwrap();
+        let mut buffer = [0u8; 6];
+        mmu.load(&other, view.address() + 8, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"shared");
+        assert_eq!(mmu.store(&other, view.address(), b"x"), Err(Fault::Page(Cause::PageStoreFault)));
+
+        // a split mapping keeps its offset into the object
+        writer.munmap::<Sv39>(&mut table, PageRange::new(start, start + 1)).unwrap();
+        Sv39::unmap(&mut table, start + 1);
+        writer.fault::<Sv39>(&mut table, start + 1, Flag::R).unwrap();
+        mmu.load(&table, (start + 1).address() + 8, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"shared");
+
+        // the frames outlive the name and the handle until the last mapping goes away
+        assert!(shm::unlink("test-shm") && shm::open("test-shm").is_none());
+        drop((object, writer));
+        table.destroy();
+        assert!(frame.upgrade().is_some());
+        reader.munmap::<Sv39>(&mut other, PageRange::new(view, view + 1)).unwrap();
+        drop(reader);
+        assert!(frame.upgrade().is_none());
+
+        other.destroy();
+    }
 }
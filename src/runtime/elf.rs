@@ -0,0 +1,441 @@
+/*!
+ELF 解析
+
+支持 64 位小端的可执行文件（ET_EXEC）与位置无关的可执行文件（ET_DYN）；ET_DYN 装载到调用者选定的基址
+
+没有解释器的 PIE 在装载前对文件内容的副本应用 RELATIVE 重定位；有解释器时重定位由解释器完成
+
+损坏的文件返回 Error::Malformed 而不会 panic
+
+# 结构体
+Image
+
+Tls
+*/
+use alloc::string::{ String, ToString };
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use xmas_elf::{ ElfFile, header, program::Type };
+
+use crate::{
+    runtime::Segment,
+    memory::{ Flag, Address, VirtAddr, VirtPageNum, PageRange },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// 文件损坏：头部或程序头表不完整、段超出文件、地址不合法等
+    Malformed(&'static str),
+    /// 文件合法但不受支持：32 位或大端文件、未知的重定位类型、缺少解释器等
+    Unsupported(&'static str),
+}
+
+/// (段, 起始虚拟地址, 段在文件中的范围)
+pub type Load = (Segment, VirtAddr, (usize, usize));
+
+/**
+线程局部存储的模板，\[address, address + file_size) 为初始值，其余填零
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tls {
+    pub address: VirtAddr,
+    pub file_size: usize,
+    pub mem_size: usize,
+    pub align: usize,
+}
+
+/**
+解析后的 ELF，地址均已加上装载偏移
+*/
+pub struct Image {
+    /// 文件内容，PIE 已应用重定位
+    pub data: Arc<[u8]>,
+    /// 装载偏移，ET_EXEC 为 0
+    pub base: usize,
+    pub entry: VirtAddr,
+    pub load: Vec<Load>,
+    /// 程序头表的虚拟地址、表项大小与表项数
+    pub phdr: (VirtAddr, usize, usize),
+    /// 解释器的路径
+    pub interp: Option<String>,
+    pub tls: Option<Tls>,
+    pub pie: bool,
+}
+
+impl Image {
+    /**
+    解析 data，ET_DYN 装载到 base 处，ET_EXEC 忽略 base
+
+    relocate 为 true 且文件为没有解释器的 PIE 时应用 RELATIVE 重定位
+    */
+    pub fn parse(data: Arc<[u8]>, base: usize, relocate: bool) -> Result<Self, Error> {
+        let elf = open(&data)?;
+        let pie = elf.header.pt2.type_().as_type() == header::Type::SharedObject;
+        let base = if pie { base } else { 0 };
+
+        let file = |offset: u64, size: u64| {
+            let start = offset as usize;
+            start.checked_add(size as usize)
+                .filter(|&end| end <= data.len())
+                .map(|end| (start, end))
+                .ok_or(Error::Malformed("segment out of file"))
+        };
+        let address = |virtual_addr: u64, size: u64| {
+            let start = (virtual_addr as usize).checked_add(base);
+            let end = start.and_then(|start| start.checked_add(size as usize));
+            match (start.and_then(VirtAddr::try_new), end.and_then(VirtAddr::try_new)) {
+                (Some(start), Some(end)) => Ok((start, end)),
+                _ => Err(Error::Malformed("segment address")),
+            }
+        };
+
+        let mut load = Vec::new();
+        let mut phdr = None;
+        let mut interp = None;
+        let mut tls = None;
+        let mut dynamic = None;
+        for i in 0..elf.header.pt2.ph_count() {
+            let program_header = elf.program_header(i).map_err(Error::Malformed)?;
+            let (file_size, mem_size) = (program_header.file_size(), program_header.mem_size());
+            match program_header.get_type().map_err(Error::Malformed)? {
+                Type::Load => {
+                    if file_size > mem_size {
+                        return Err(Error::Malformed("file size exceeds memory size"));
+                    }
+                    let range = file(program_header.offset(), file_size)?;
+                    let (start, end) = address(program_header.virtual_addr(), mem_size)?;
+
+                    let ph_flags = program_header.flags();
+                    let mut flag = Flag::U;
+                    if ph_flags.is_read() { flag |= Flag::R; }
+                    if ph_flags.is_write() { flag |= Flag::W; }
+                    if ph_flags.is_execute() { flag |= Flag::X; }
+
+                    load.push((Segment { range: PageRange::new(start.floor(), end.ceil()), flag }, start, range));
+                },
+                Type::Phdr => phdr = Some(address(program_header.virtual_addr(), 0)?.0),
+                Type::Interp => {
+                    let (start, end) = file(program_header.offset(), file_size)?;
+                    let path = data[start..end].split(|&byte| byte == 0).next().unwrap_or_default();
+                    let path = core::str::from_utf8(path).map_err(|_| Error::Malformed("interpreter path"))?;
+                    interp = Some(path.to_string());
+                },
+                Type::Tls => {
+                    file(program_header.offset(), file_size)?;
+                    tls = Some(Tls {
+                        address: address(program_header.virtual_addr(), mem_size)?.0,
+                        file_size: file_size as usize,
+                        mem_size: mem_size as usize,
+                        align: program_header.align() as usize,
+                    });
+                },
+                Type::Dynamic => dynamic = Some(file(program_header.offset(), file_size)?),
+                _ => {},
+            }
+        }
+        if load.is_empty() {
+            return Err(Error::Malformed("no loadable segment"));
+        }
+
+        // without PT_PHDR the table is found through the segment that contains it
+        let ph_offset = elf.header.pt2.ph_offset() as usize;
+        let phdr = match phdr {
+            Some(phdr) => phdr,
+            None => load.iter()
+                .find(|(_, _, (start, end))| (*start..*end).contains(&ph_offset))
+                .map_or(VirtAddr::new(0), |(_, address, (start, _))| *address + (ph_offset - start)),
+        };
+        let phdr = (phdr, elf.header.pt2.ph_entry_size() as usize, elf.header.pt2.ph_count() as usize);
+
+        let entry = (elf.header.pt2.entry_point() as usize).checked_add(base)
+            .and_then(VirtAddr::try_new)
+            .ok_or(Error::Malformed("entry point"))?;
+
+        let data = match dynamic {
+            Some(dynamic) if pie && relocate && interp.is_none() => {
+                let machine = u16::from_le_bytes([data[18], data[19]]);
+                apply(&data, machine, dynamic, &load, base)?
+            },
+            _ => data.clone(),
+        };
+
+        Ok(Self { data, base, entry, load, phdr, interp, tls, pie })
+    }
+    /**
+    所有 Load 段覆盖的页号区间
+    */
+    pub fn span(&self) -> PageRange<VirtPageNum> {
+        let start = self.load.iter().map(|(segment, _, _)| segment.range.start).min().unwrap_or_default();
+        let end = self.load.iter().map(|(segment, _, _)| segment.range.end).max().unwrap_or_default();
+
+        PageRange::new(start, end)
+    }
+    /**
+    辅助向量的 (类型, 值)，不含结尾的 AT_NULL
+
+    interp_base 为解释器的装载偏移，没有解释器时为 0
+    */
+    pub fn auxv(&self, interp_base: usize) -> Vec<(usize, usize)> {
+        let (phdr, phent, phnum) = self.phdr;
+        Vec::from([
+            (auxv::PHDR, phdr.as_usize()),
+            (auxv::PHENT, phent),
+            (auxv::PHNUM, phnum),
+            (auxv::PAGESZ, Address::address(1)),
+            (auxv::BASE, interp_base),
+            (auxv::FLAGS, 0),
+            (auxv::ENTRY, self.entry.as_usize()),
+        ])
+    }
+}
+
+/**
+辅助向量的类型
+*/
+pub mod auxv {
+    pub const NULL: usize = 0;
+    pub const PHDR: usize = 3;
+    pub const PHENT: usize = 4;
+    pub const PHNUM: usize = 5;
+    pub const PAGESZ: usize = 6;
+    pub const BASE: usize = 7;
+    pub const FLAGS: usize = 8;
+    pub const ENTRY: usize = 9;
+    pub const RANDOM: usize = 25;
+}
+
+/**
+检查头部与程序头表的完整性后打开文件
+*/
+fn open(data: &[u8]) -> Result<ElfFile<'_>, Error> {
+    if data.len() < config::HEADER {
+        return Err(Error::Malformed("file shorter than the ELF header"));
+    }
+    let elf = ElfFile::new(data).map_err(Error::Malformed)?;
+    if elf.header.pt1.class() != header::Class::SixtyFour {
+        return Err(Error::Unsupported("not a 64-bit file"));
+    }
+    if elf.header.pt1.data() != header::Data::LittleEndian {
+        return Err(Error::Unsupported("not a little-endian file"));
+    }
+    match elf.header.pt2.type_().as_type() {
+        header::Type::Executable | header::Type::SharedObject => {},
+        _ => return Err(Error::Unsupported("not an executable")),
+    }
+
+    let pt2 = &elf.header.pt2;
+    let (count, size) = (pt2.ph_count() as usize, pt2.ph_entry_size() as usize);
+    let end = count.checked_mul(size).and_then(|len| len.checked_add(pt2.ph_offset() as usize));
+    if count > 0 && (size < config::PROGRAM_HEADER || pt2.ph_offset() == 0 || end.is_none_or(|end| end > data.len())) {
+        return Err(Error::Malformed("program header table out of file"));
+    }
+
+    Ok(elf)
+}
+
+/**
+在 data 的副本上应用 dynamic 段中的 RELATIVE 重定位，目标值为 base + addend
+*/
+fn apply(data: &[u8], machine: u16, dynamic: (usize, usize), load: &[Load], base: usize) -> Result<Arc<[u8]>, Error> {
+    let read = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap()) as usize;
+
+    let (mut rela, mut size, mut entry) = (None, 0, config::RELA);
+    for at in (dynamic.0..dynamic.1).step_by(16).take_while(|at| at + 16 <= dynamic.1) {
+        match read(at) {
+            config::DT_NULL => break,
+            config::DT_RELA => rela = Some(read(at + 8)),
+            config::DT_RELASZ => size = read(at + 8),
+            config::DT_RELAENT => entry = read(at + 8),
+            config::DT_REL | config::DT_RELR => return Err(Error::Unsupported("relocations without addend")),
+            _ => {},
+        }
+    }
+
+    let mut image = data.to_vec();
+    let Some(rela) = rela else {
+        return Ok(image.into());
+    };
+    let relative = match machine {
+        config::EM_RISCV => config::R_RISCV_RELATIVE,
+        config::EM_X86_64 => config::R_X86_64_RELATIVE,
+        config::EM_AARCH64 => config::R_AARCH64_RELATIVE,
+        _ => return Err(Error::Unsupported("relocations for this machine")),
+    };
+    if entry < config::RELA {
+        return Err(Error::Malformed("relocation entry size"));
+    }
+    let table = offset(load, rela.wrapping_add(base), size).ok_or(Error::Malformed("relocation table out of file"))?;
+
+    for at in (table..table + size / entry * entry).step_by(entry) {
+        let (target, info, addend) = (read(at), read(at + 8), read(at + 16));
+        if info & 0xffff_ffff != relative {
+            return Err(Error::Unsupported("relocation type"));
+        }
+
+        let target = offset(load, target.wrapping_add(base), 8).ok_or(Error::Unsupported("relocation outside file"))?;
+        image[target..target + 8].copy_from_slice(&(base.wrapping_add(addend) as u64).to_le_bytes());
+    }
+
+    Ok(image.into())
+}
+
+/**
+虚拟地址 \[address, address + len) 在文件中的偏移，须完全位于某个段的文件部分
+*/
+fn offset(load: &[Load], address: usize, len: usize) -> Option<usize> {
+    load.iter().find_map(|(_, start, range)| {
+        let delta = address.checked_sub(start.as_usize())?;
+        (delta.checked_add(len)? <= range.1 - range.0).then_some(range.0 + delta)
+    })
+}
+
+mod config {
+    /// ELF64 头部的字节数
+    pub const HEADER: usize = 64;
+    /// ELF64 程序头的字节数
+    pub const PROGRAM_HEADER: usize = 56;
+    /// Elf64_Rela 的字节数
+    pub const RELA: usize = 24;
+
+    pub const DT_NULL: usize = 0;
+    pub const DT_RELA: usize = 7;
+    pub const DT_RELASZ: usize = 8;
+    pub const DT_RELAENT: usize = 9;
+    pub const DT_REL: usize = 17;
+    pub const DT_RELR: usize = 36;
+
+    pub const EM_X86_64: u16 = 0x3e;
+    pub const EM_AARCH64: u16 = 0xb7;
+    pub const EM_RISCV: u16 = 0xf3;
+
+    pub const R_X86_64_RELATIVE: usize = 8;
+    pub const R_AARCH64_RELATIVE: usize = 1027;
+    pub const R_RISCV_RELATIVE: usize = 3;
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use crate::memory::{ Flag, VirtAddr, VirtPageNum, PageRange };
+    use super::{ Image, Error, Tls };
+
+    /// (类型, 权限, 虚拟地址, 内容, 内存大小)
+    pub type Program<'a> = (u32, u32, usize, &'a [u8], usize);
+
+    pub const PT_LOAD: u32 = 1;
+    pub const PT_DYNAMIC: u32 = 2;
+    pub const PT_INTERP: u32 = 3;
+    pub const PT_TLS: u32 = 7;
+
+    /**
+    构造 RISC-V 的 ELF64 文件，kind 为 2（ET_EXEC）或 3（ET_DYN），各段的内容依次放在程序头表之后
+    */
+    pub fn build(kind: u16, entry: usize, program: &[Program]) -> Vec<u8> {
+        let mut elf = Vec::from(*b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+        elf.extend_from_slice(&kind.to_le_bytes());
+        elf.extend_from_slice(&0xf3u16.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        for value in [entry as u64, 64, 0] {
+            elf.extend_from_slice(&value.to_le_bytes());
+        }
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        for value in [64u16, 56, program.len() as u16, 64, 0, 0] {
+            elf.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut offset = 64 + 56 * program.len();
+        for &(kind, flags, address, content, size) in program {
+            elf.extend_from_slice(&kind.to_le_bytes());
+            elf.extend_from_slice(&flags.to_le_bytes());
+            for value in [offset, address, address, content.len(), size, 0x1000] {
+                elf.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+            offset += content.len();
+        }
+        for (_, _, _, content, _) in program {
+            elf.extend_from_slice(content);
+        }
+
+        elf
+    }
+
+    fn words(words: &[u64]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    /**
+    静态链接的 PIE：数据段开头的指针需重定位为 base + 0x40
+    */
+    fn pie(kind: u64) -> Vec<u8> {
+        let text = [0x13u8; 0x100];
+        let data = words(&[0, 7]);
+        let rela = words(&[0x2000, kind, 0x40]);
+        let dynamic = words(&[7, 0x3000, 8, 24, 9, 24, 0, 0]);
+
+        build(3, 0x10, &[
+            (PT_LOAD, 5, 0, &text, text.len()),
+            (PT_LOAD, 6, 0x2000, &data, 0x1800),
+            (PT_LOAD, 4, 0x3000, &rela, rela.len()),
+            (PT_DYNAMIC, 4, 0x4000, &dynamic, dynamic.len()),
+            (PT_TLS, 4, 0x2008, &data[8..], 0x20),
+        ])
+    }
+
+    #[test]
+    fn relocate() {
+        let base = 0x40_0000;
+        let image = Image::parse(pie(3).into(), base, true).unwrap();
+        assert!(image.pie && image.interp.is_none());
+        assert_eq!(image.entry, VirtAddr::new(base + 0x10));
+        assert_eq!(image.load[1].0.range, PageRange::new(VirtPageNum::new(0x402), VirtPageNum::new(0x404)));
+        assert_eq!(image.span(), PageRange::new(VirtPageNum::new(0x400), VirtPageNum::new(0x404)));
+        assert_eq!(image.tls, Some(Tls { address: VirtAddr::new(base + 0x2008), file_size: 8, mem_size: 0x20, align: 0x1000 }));
+
+        let (_, _, (start, _)) = image.load[1];
+        assert_eq!(image.data[start..start + 16], words(&[base as u64 + 0x40, 7]));
+
+        // without relocation the file is untouched
+        let image = Image::parse(pie(3).into(), base, false).unwrap();
+        assert_eq!(image.data[..], pie(3)[..]);
+
+        let image = Image::parse(pie(2).into(), base, true);
+        assert_eq!(image.err(), Some(Error::Unsupported("relocation type")));
+    }
+
+    #[test]
+    fn malformed() {
+        let elf = pie(3);
+        let parse = |elf: &[u8]| Image::parse(Arc::from(elf), 0, true).err();
+
+        assert!(matches!(parse(&elf[..20]), Some(Error::Malformed(_))));
+        assert!(matches!(parse(&elf[..200]), Some(Error::Malformed(_))));
+        assert!(matches!(parse(b"\x7fELF"), Some(Error::Malformed(_))));
+
+        let mut corrupt = elf.clone();
+        corrupt[0] = 0;
+        assert!(matches!(parse(&corrupt), Some(Error::Malformed(_))));
+
+        // too many program headers
+        let mut corrupt = elf.clone();
+        corrupt[56] = 0xff;
+        assert!(matches!(parse(&corrupt), Some(Error::Malformed(_))));
+
+        // file size of the first segment beyond the file
+        let mut corrupt = elf.clone();
+        corrupt[64 + 32..64 + 40].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(parse(&corrupt), Some(Error::Malformed(_))));
+
+        let mut corrupt = elf.clone();
+        corrupt[4] = 1;
+        assert!(matches!(parse(&corrupt), Some(Error::Unsupported(_))));
+
+        let flag = Flag::U | Flag::R | Flag::X;
+        let image = Image::parse(build(2, 0x1_0000, &[(PT_LOAD, 5, 0x1_0000, &[0; 8], 8)]).into(), 0x40_0000, true).unwrap();
+        assert!(!image.pie && image.base == 0);
+        assert_eq!(image.load[0].0.flag, flag);
+        // the table is not loaded
+        assert_eq!(image.phdr, (VirtAddr::new(0), 56, 1));
+    }
+}
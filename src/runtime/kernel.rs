@@ -0,0 +1,232 @@
+/*!
+内核地址空间的构建
+
+Builder 记录具名的区域，build 时检查区域不为空、互不重叠，并拒绝未经 region_wx 允许的可写且可执行的映射
+
+栈由 stacks 依次放置，每个栈下方有一个不映射的保护页面，可用于 per-CPU 与 per-thread 的内核栈
+
+# 结构体
+Builder
+
+Map
+
+Region
+*/
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{
+    info_module,
+    runtime::{ Segment, address_space::AddressSpace },
+    memory::{ Flag, VirtAddr, VirtPageNum, PageRange },
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// 区域为空
+    Empty(String),
+    /// 两个区域重叠
+    Overlap(String, String),
+    /// 区域可写且可执行，但未经 region_wx 允许
+    WriteExecute(String),
+}
+
+/**
+具名的区域，保护页面的 flag 为空
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub range: PageRange<VirtPageNum>,
+    pub flag: Flag,
+    /// 是否允许可写且可执行
+    pub wx: bool,
+}
+
+impl Region {
+    #[inline]
+    pub fn is_guard(&self) -> bool {
+        self.flag.is_empty()
+    }
+}
+
+pub struct Builder {
+    entry: VirtAddr,
+    region: Vec<Region>,
+}
+
+impl Builder {
+    pub fn new(entry: VirtAddr) -> Self {
+        Self { entry, region: Vec::new() }
+    }
+    /**
+    添加恒等映射等具名区域，flag 不能同时包含 W 与 X
+    */
+    pub fn region(mut self, name: &str, range: PageRange<VirtPageNum>, flag: Flag) -> Self {
+        self.region.push(Region { name: String::from(name), range, flag, wx: false });
+        self
+    }
+    /**
+    同 region，但允许可写且可执行的映射
+    */
+    pub fn region_wx(mut self, name: &str, range: PageRange<VirtPageNum>, flag: Flag) -> Self {
+        self.region.push(Region { name: String::from(name), range, flag, wx: true });
+        self
+    }
+    /**
+    从 start 开始依次放置 count 个 size 页的可读写栈，每个栈下方有一个保护页面
+
+    第 i 个栈的区域名为 "{name} {i}"，其保护页面为 "{name} {i} guard"
+    */
+    pub fn stacks(mut self, name: &str, start: VirtPageNum, count: usize, size: usize) -> Self {
+        for i in 0..count {
+            let guard = start + i * (size + 1);
+            self.region.push(Region {
+                name: format!("{} {} guard", name, i),
+                range: PageRange::new(guard, guard + 1),
+                flag: Flag::empty(),
+                wx: false,
+            });
+            self.region.push(Region {
+                name: format!("{} {}", name, i),
+                range: PageRange::new(guard + 1, guard + 1 + size),
+                flag: Flag::R | Flag::W,
+                wx: false,
+            });
+        }
+        self
+    }
+    /**
+    检查所有区域并得到地址空间，segement 按地址排列且不含保护页面
+    */
+    pub fn build(mut self) -> Result<(AddressSpace, Map), Error> {
+        self.region.sort_by_key(|region| region.range.start);
+
+        let mut last: Option<&Region> = None;
+        for region in &self.region {
+            if region.range.is_empty() {
+                return Err(Error::Empty(region.name.clone()));
+            }
+            if region.flag.contains(Flag::W | Flag::X) && !region.wx {
+                return Err(Error::WriteExecute(region.name.clone()));
+            }
+            if let Some(last) = last.filter(|last| last.range.end > region.range.start) {
+                return Err(Error::Overlap(last.name.clone(), region.name.clone()));
+            }
+            if last.is_none_or(|last| region.range.end > last.range.end) {
+                last = Some(region);
+            }
+        }
+
+        let mut space = AddressSpace::empty();
+        space.entry = self.entry;
+        space.segement = self.region.iter()
+            .filter(|region| !region.is_guard())
+            .map(|region| Segment { range: region.range, flag: region.flag })
+            .collect();
+        space.end = self.region.iter().map(|region| region.range.end).max().unwrap_or_default();
+        space.brk = space.end.address();
+
+        Ok((space, Map { region: self.region }))
+    }
+}
+
+/**
+构建得到的内核地址空间的布局，区域按地址排列
+*/
+pub struct Map {
+    pub region: Vec<Region>,
+}
+
+impl Map {
+    pub fn get(&self, name: &str) -> Option<&Region> {
+        self.region.iter().find(|region| region.name == name)
+    }
+    /**
+    page_number 所在的保护页面
+    */
+    pub fn guard(&self, page_number: VirtPageNum) -> Option<&Region> {
+        self.region.iter().find(|region| region.is_guard() && region.range.contains(page_number))
+    }
+    /**
+    每个区域一行：\[起始地址, 结束地址) 权限 名字
+    */
+    pub fn lines(&self) -> Vec<String> {
+        self.region.iter().map(|region| {
+            let permission: String = [(Flag::R, 'r'), (Flag::W, 'w'), (Flag::X, 'x')].iter()
+                .map(|(flag, name)| if region.flag.contains(*flag) { *name } else { '-' })
+                .collect();
+
+            format!("[{:#x}, {:#x}) {} {}", region.range.start.address().as_usize(), region.range.end.address().as_usize(), permission, region.name)
+        }).collect()
+    }
+    /**
+    通过 info_module 输出布局
+    */
+    pub fn dump(&self) {
+        info_module("kernel address space", self.lines());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+    use crate::memory::{ Flag, VirtAddr, VirtPageNum, PageRange };
+    use super::{ Builder, Error };
+
+    fn range(start: usize, end: usize) -> PageRange<VirtPageNum> {
+        PageRange::new(VirtPageNum::new(start), VirtPageNum::new(end))
+    }
+
+    #[test]
+    fn build() {
+        let builder = Builder::new(VirtAddr::new(0x8020_0000))
+            .region("text", range(0x80200, 0x80210), Flag::R | Flag::X)
+            .region("data", range(0x80210, 0x80220), Flag::R | Flag::W)
+            .region("mmio", range(0x10000, 0x10001), Flag::R | Flag::W)
+            .stacks("cpu stack", VirtPageNum::new(0x90000), 2, 4)
+            .stacks("thread stack", VirtPageNum::new(0x90010), 1, 2);
+        let (space, map) = builder.build().unwrap();
+
+        assert_eq!(space.entry, VirtAddr::new(0x8020_0000));
+        assert_eq!(space.end, VirtPageNum::new(0x90013));
+        let segement: Vec<_> = space.segement.iter().map(|segment| segment.range).collect();
+        assert_eq!(segement, [
+            range(0x10000, 0x10001), range(0x80200, 0x80210), range(0x80210, 0x80220),
+            range(0x90001, 0x90005), range(0x90006, 0x9000a), range(0x90011, 0x90013),
+        ]);
+        assert_eq!(map.get("cpu stack 1").unwrap().range, range(0x90006, 0x9000a));
+        assert_eq!(map.guard(VirtPageNum::new(0x90010)).unwrap().name, "thread stack 0 guard");
+        assert!(map.guard(VirtPageNum::new(0x90011)).is_none());
+
+        let lines = map.lines();
+        assert_eq!(lines[0], "[0x10000000, 0x10001000) rw- mmio");
+        assert_eq!(lines[3], "[0x90000000, 0x90001000) --- cpu stack 0 guard");
+        map.dump();
+    }
+
+    #[test]
+    fn reject() {
+        let text = Builder::new(VirtAddr::new(0)).region("text", range(0x100, 0x110), Flag::R | Flag::X);
+
+        let overlap = text.region("data", range(0x10f, 0x120), Flag::R | Flag::W).build();
+        assert_eq!(overlap.err(), Some(Error::Overlap("text".into(), "data".into())));
+
+        // a region inside an earlier, larger one
+        let overlap = Builder::new(VirtAddr::new(0))
+            .region("frame", range(0x100, 0x200), Flag::R | Flag::W)
+            .region("a", range(0x110, 0x120), Flag::R)
+            .region("b", range(0x150, 0x160), Flag::R)
+            .build();
+        assert!(matches!(overlap.err(), Some(Error::Overlap(_, _))));
+
+        let wx = Builder::new(VirtAddr::new(0)).region("jit", range(0x100, 0x101), Flag::R | Flag::W | Flag::X).build();
+        assert_eq!(wx.err(), Some(Error::WriteExecute("jit".into())));
+        let wx = Builder::new(VirtAddr::new(0)).region_wx("jit", range(0x100, 0x101), Flag::R | Flag::W | Flag::X).build();
+        assert!(wx.is_ok());
+
+        let empty = Builder::new(VirtAddr::new(0)).region("empty", range(0x100, 0x100), Flag::R).build();
+        assert_eq!(empty.err(), Some(Error::Empty("empty".into())));
+    }
+}
@@ -5,17 +5,19 @@ heap
 */
 
 pub mod address_space;
+pub mod elf;
+pub mod kernel;
 
-use crate::memory::Flag;
+use crate::memory::{ Flag, VirtPageNum, PageRange };
 
 /**
-range 为页号
+range 为页号区间 \[start, end)
 
 grouth true 代表向高地址方向增长，false 代表向低地址方向增长
 */
 #[derive(Clone, Copy)]
 pub struct Segment {
-    pub range: (usize, usize),
+    pub range: PageRange<VirtPageNum>,
     pub flag: Flag,
 }
 
@@ -3,13 +3,47 @@ pub trait Lib: Hal {
     fn syscall(id: usize, args: [usize; 3]) -> isize {
         match id {
             config::WRITE => Self::write(args[0], args[1] as *const u8, args[2]),
+            config::EXEC => Self::exec(args[0] as *const u8, args[1] as *const *const u8, args[2] as *const *const u8),
+            config::BRK => Self::brk(args[0]),
+            config::MMAP => Self::mmap(args[0], args[1], args[2]),
+            config::MUNMAP => Self::munmap(args[0], args[1]),
+            config::MPROTECT => Self::mprotect(args[0], args[1], args[2]),
             _ => panic!("Unsupported syscall id: {}.", id),
         }
     }
 }
 
 pub trait Hal {
+    /**
+    buf 为用户空间地址，可通过 runtime::address_space::AddressSpace::translated_byte_buffer 转换
+    */
     fn write(fd: usize, buf: *const u8, len: usize) -> isize;
+    /**
+    path、argv 与 envp 为用户空间地址，argv 与 envp 以空指针结尾，可通过 concurrency::process::Lib::exec 替换地址空间
+
+    成功时不返回到原程序
+    */
+    fn exec(path: *const u8, argv: *const *const u8, envp: *const *const u8) -> isize;
+    /**
+    设置程序断点，address 为 0 时只返回当前断点，可通过 runtime::address_space::AddressSpace::brk 实现
+
+    返回新的断点，失败时返回原断点
+    */
+    fn brk(address: usize) -> isize;
+    /**
+    建立 len 字节的匿名私有映射，start 为 0 时选取空闲区域，port 的第 0、1、2 位分别为读、写、执行权限，可通过 AddressSpace::mmap 实现
+
+    返回映射的起始地址，失败时返回 -1
+    */
+    fn mmap(start: usize, len: usize, port: usize) -> isize;
+    /**
+    解除 \[start, start + len) 的映射，可通过 AddressSpace::munmap 实现
+    */
+    fn munmap(start: usize, len: usize) -> isize;
+    /**
+    修改 \[start, start + len) 的权限，port 同 mmap，可通过 AddressSpace::mprotect 实现，之后需刷新 TLB
+    */
+    fn mprotect(start: usize, len: usize, port: usize) -> isize;
 }
 
 pub mod config {
@@ -34,8 +68,12 @@ pub mod config {
     pub const KILL: usize = 129;
     pub const GET_TIME: usize = 169;
     pub const GETPID: usize = 172;
+    pub const BRK: usize = 214;
+    pub const MUNMAP: usize = 215;
     pub const FORK: usize = 220;
     pub const EXEC: usize = 221;
+    pub const MMAP: usize = 222;
+    pub const MPROTECT: usize = 226;
     pub const WAITPID: usize = 260;
     pub const THREAD_CREATE: usize = 1000;
     pub const GETTID: usize = 1001;